@@ -0,0 +1,190 @@
+use color_eyre::{eyre::ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// How mouse movement speed scales the sensitivity
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(tag = "profile", rename_all = "snake_case")]
+pub enum Acceleration {
+	/// Always use the sensitivity as is
+	#[default]
+	Flat,
+	/// Speed the pointer up the faster the mouse moves, like libinput's adaptive profile
+	Adaptive(AdaptiveCurve),
+	/// Look up the gain for a speed from a list of points
+	Custom(CustomCurve),
+}
+impl Acceleration {
+	/// The multiplier for a movement of `distance` pixels over `delta` seconds
+	pub fn gain(&self, distance: f32, delta: f32) -> f32 {
+		if delta <= 0.0 {
+			return 1.0;
+		}
+		let speed = distance / delta;
+		match self {
+			Acceleration::Flat => 1.0,
+			Acceleration::Adaptive(curve) => curve.gain(speed),
+			Acceleration::Custom(curve) => curve.gain(speed),
+		}
+	}
+
+	/// Check that every value is a number and no gain is negative, so the pointer can't freeze or turn backwards
+	pub fn validate(&self) -> Result<()> {
+		match self {
+			Acceleration::Flat => {}
+			Acceleration::Adaptive(curve) => {
+				for (name, value) in [
+					("threshold", curve.threshold),
+					("accel", curve.accel),
+					("max_gain", curve.max_gain),
+				] {
+					ensure!(
+						value.is_finite() && value >= 0.0,
+						"acceleration {name} has to be a number 0 or above, not {value}"
+					);
+				}
+			}
+			Acceleration::Custom(curve) => {
+				for [speed, gain] in &curve.points {
+					ensure!(
+						speed.is_finite() && gain.is_finite() && *gain >= 0.0,
+						"acceleration points have to be numbers with gains 0 or above, not [{speed}, {gain}]"
+					);
+				}
+				ensure!(
+					curve.points.windows(2).all(|pair| pair[0][0] <= pair[1][0]),
+					"acceleration points have to be sorted by speed"
+				);
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdaptiveCurve {
+	/// speed in pixels per second below which there's no acceleration
+	pub threshold: f32,
+	/// gain added for every 1000 pixels per second above the threshold
+	pub accel: f32,
+	/// the gain never goes above this
+	pub max_gain: f32,
+}
+impl Default for AdaptiveCurve {
+	fn default() -> Self {
+		AdaptiveCurve {
+			threshold: 300.0,
+			accel: 1.0,
+			max_gain: 3.0,
+		}
+	}
+}
+impl AdaptiveCurve {
+	fn gain(&self, speed: f32) -> f32 {
+		let excess = (speed - self.threshold).max(0.0);
+		(1.0 + excess * self.accel / 1000.0).min(self.max_gain.max(1.0))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CustomCurve {
+	/// `[speed, gain]` pairs with speed in pixels per second, sorted by speed.
+	/// The gain is interpolated between points and held at the ends.
+	pub points: Vec<[f32; 2]>,
+}
+impl CustomCurve {
+	fn gain(&self, speed: f32) -> f32 {
+		let Some(first) = self.points.first() else {return 1.0};
+		if speed <= first[0] {
+			return first[1];
+		}
+		for pair in self.points.windows(2) {
+			let ([speed_a, gain_a], [speed_b, gain_b]) = (pair[0], pair[1]);
+			// speeds up to `speed_a` were already handled, so `speed_b` is past it and this can't divide by 0
+			if speed <= speed_b {
+				let t = (speed - speed_a) / (speed_b - speed_a);
+				return gain_a + (gain_b - gain_a) * t;
+			}
+		}
+		self.points.last().unwrap()[1]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn custom(points: &[[f32; 2]]) -> CustomCurve {
+		CustomCurve {
+			points: points.to_vec(),
+		}
+	}
+
+	#[test]
+	fn custom_curve_interpolates_between_points() {
+		let curve = custom(&[[0.0, 0.5], [1000.0, 2.0], [2000.0, 3.0]]);
+		assert_eq!(curve.gain(500.0), 1.25);
+		assert_eq!(curve.gain(1000.0), 2.0);
+		assert_eq!(curve.gain(1500.0), 2.5);
+	}
+
+	#[test]
+	fn custom_curve_holds_the_ends() {
+		let curve = custom(&[[100.0, 0.5], [1000.0, 2.0]]);
+		assert_eq!(curve.gain(0.0), 0.5);
+		assert_eq!(curve.gain(5000.0), 2.0);
+	}
+
+	#[test]
+	fn custom_curve_steps_at_repeated_speeds() {
+		let curve = custom(&[[0.0, 1.0], [500.0, 1.0], [500.0, 2.0], [1000.0, 2.0]]);
+		assert_eq!(curve.gain(499.0), 1.0);
+		assert_eq!(curve.gain(500.0), 1.0);
+		assert_eq!(curve.gain(501.0), 2.0);
+	}
+
+	#[test]
+	fn unsorted_custom_curve_stays_finite() {
+		let curve = custom(&[[1000.0, 2.0], [0.0, 0.5], [0.0, 3.0]]);
+		for speed in [0.0, 500.0, 1000.0, 5000.0] {
+			assert!(curve.gain(speed).is_finite());
+		}
+	}
+
+	#[test]
+	fn empty_custom_curve_is_flat() {
+		assert_eq!(custom(&[]).gain(1000.0), 1.0);
+		assert_eq!(custom(&[[0.0, 2.0]]).gain(1000.0), 2.0);
+	}
+
+	#[test]
+	fn adaptive_curve_starts_at_the_threshold() {
+		let curve = AdaptiveCurve {
+			threshold: 300.0,
+			accel: 1.0,
+			max_gain: 3.0,
+		};
+		assert_eq!(curve.gain(100.0), 1.0);
+		assert_eq!(curve.gain(300.0), 1.0);
+		assert_eq!(curve.gain(800.0), 1.5);
+		assert_eq!(curve.gain(10000.0), 3.0);
+	}
+
+	#[test]
+	fn adaptive_curve_never_slows_down() {
+		let curve = AdaptiveCurve {
+			threshold: 0.0,
+			accel: 1.0,
+			max_gain: 0.5,
+		};
+		assert_eq!(curve.gain(1000.0), 1.0);
+	}
+
+	#[test]
+	fn no_time_means_no_acceleration() {
+		let acceleration = Acceleration::Adaptive(AdaptiveCurve::default());
+		assert_eq!(acceleration.gain(100.0, 0.0), 1.0);
+		assert_eq!(Acceleration::Flat.gain(100.0, 0.001), 1.0);
+	}
+}
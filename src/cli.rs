@@ -0,0 +1,76 @@
+use azimuth::settings::Settings;
+use clap::{ArgAction, Args, Parser};
+use std::path::PathBuf;
+use tracing::Level;
+
+/// Desktop style pointer all around you from non-spatial input
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+	/// Settings file to use instead of `$XDG_CONFIG_HOME/azimuth/config.toml`
+	#[arg(short, long)]
+	pub config: Option<PathBuf>,
+	/// Most verbose level of log messages to print, `debug` shows hit testing and keyboard routing
+	#[arg(long, env = "AZIMUTH_LOG", default_value_t = Level::INFO)]
+	pub log_level: Level,
+	/// Print the default settings file and exit
+	#[arg(long)]
+	pub print_default_config: bool,
+	#[command(flatten)]
+	pub overrides: Overrides,
+}
+
+/// Settings given on the command line, these take priority over the settings file
+#[derive(Debug, Clone, Args)]
+pub struct Overrides {
+	/// Degrees per pixel of mouse movement
+	#[arg(short, long)]
+	pub sensitivity: Option<f32>,
+	/// How far in front of the pointer the cursor sits, in meters
+	#[arg(long)]
+	pub cursor_distance: Option<f32>,
+	/// Invert vertical mouse movement, `--invert-y false` turns it off even if the settings file has it on
+	#[arg(long, action = ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+	pub invert_y: Option<bool>,
+}
+impl Overrides {
+	pub fn apply(&self, settings: &mut Settings) {
+		if let Some(sensitivity) = self.sensitivity {
+			settings.sensitivity = sensitivity;
+		}
+		if let Some(cursor_distance) = self.cursor_distance {
+			settings.cursor.distance = cursor_distance;
+		}
+		if let Some(invert_y) = self.invert_y {
+			settings.invert_y = invert_y;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn invert_y(args: &[&str], from_file: bool) -> bool {
+		let cli = Cli::try_parse_from([&["azimuth"], args].concat()).unwrap();
+		let mut settings = Settings {
+			invert_y: from_file,
+			..Default::default()
+		};
+		cli.overrides.apply(&mut settings);
+		settings.invert_y
+	}
+
+	#[test]
+	fn invert_y_overrides_the_file_either_way() {
+		assert!(invert_y(&["--invert-y"], false));
+		assert!(invert_y(&["--invert-y", "true"], false));
+		assert!(!invert_y(&["--invert-y", "false"], true));
+	}
+
+	#[test]
+	fn no_invert_y_keeps_the_file() {
+		assert!(invert_y(&[], true));
+		assert!(!invert_y(&[], false));
+	}
+}
@@ -0,0 +1,122 @@
+use crate::settings::{CursorSettings, RaySettings};
+use color::{rgba, Rgba};
+use stardust_xr_fusion::drawable::LinePoint;
+use stardust_xr_molecules::lines::{circle, make_line_points};
+use std::f32::consts::FRAC_PI_4;
+
+/// What the cursor shows, so you can tell whether a click will land anywhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorState {
+	/// Not pointing at anything
+	Idle,
+	/// Pointing at an input handler
+	Hover,
+	/// Pointing at something that takes keyboard input
+	KeyboardTarget,
+	/// Select is held
+	Select,
+	/// Grab is held
+	Grab,
+}
+impl CursorState {
+	pub fn new(hovering: bool, keyboard_target: bool, select: bool, grab: bool) -> Self {
+		if grab {
+			CursorState::Grab
+		} else if select {
+			CursorState::Select
+		} else if keyboard_target {
+			CursorState::KeyboardTarget
+		} else if hovering {
+			CursorState::Hover
+		} else {
+			CursorState::Idle
+		}
+	}
+
+	/// `scale` is how much bigger than the settings the cursor is drawn
+	pub fn points(&self, cursor: &CursorSettings, scale: f32) -> Vec<LinePoint> {
+		let (segments, start_angle, radius, color) = match self {
+			CursorState::Idle => (8, 0.0, cursor.size, cursor.color),
+			CursorState::Hover => (16, 0.0, cursor.size * 1.5, cursor.hover_color),
+			CursorState::KeyboardTarget => (4, FRAC_PI_4, cursor.size * 1.5, cursor.keyboard_color),
+			CursorState::Select => (16, 0.0, cursor.size * 0.75, cursor.select_color),
+			CursorState::Grab => (4, 0.0, cursor.size * 1.5, cursor.grab_color),
+		};
+		make_line_points(
+			&circle(segments, start_angle, radius),
+			cursor.thickness * scale,
+			to_rgba(color),
+		)
+	}
+}
+
+/// Hits closer than this many meters are ignored when placing the cursor and ray,
+/// since the pointer is inside whatever it hit and they'd shrink to nothing
+pub const NEAR_DISTANCE: f32 = 0.01;
+
+/// `distance` of a hit, unless it's too close to draw anything at
+pub fn drawable_distance(distance: Option<f32>) -> Option<f32> {
+	distance.filter(|distance| *distance > NEAR_DISTANCE)
+}
+
+/// How far away the cursor sits and how much it's scaled, given the distance to what the pointer hit
+pub fn cursor_placement(hit_distance: Option<f32>, cursor: &CursorSettings) -> (f32, f32) {
+	let distance = drawable_distance(hit_distance)
+		.filter(|_| cursor.snap)
+		.unwrap_or(cursor.distance);
+	(distance, distance / cursor.distance)
+}
+
+/// A line along the pointer's ray `length` meters long
+pub fn ray_points(ray: &RaySettings, length: f32) -> Vec<LinePoint> {
+	let [r, g, b, _] = ray.color;
+	vec![
+		LinePoint {
+			point: [0.0; 3].into(),
+			thickness: ray.thickness,
+			color: to_rgba([r, g, b, 0.0]),
+		},
+		LinePoint {
+			point: [0.0, 0.0, -length].into(),
+			thickness: ray.thickness,
+			color: to_rgba(ray.color),
+		},
+	]
+}
+
+fn to_rgba([r, g, b, a]: [f32; 4]) -> Rgba<f32> {
+	rgba!(r, g, b, a)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cursor_scales_with_the_hit_distance() {
+		let cursor = CursorSettings::default();
+		let (distance, scale) = cursor_placement(Some(cursor.distance * 2.0), &cursor);
+		assert_eq!(distance, cursor.distance * 2.0);
+		assert_eq!(scale, 2.0);
+	}
+
+	#[test]
+	fn cursor_stays_put_without_a_hit_or_snapping() {
+		let cursor = CursorSettings::default();
+		assert_eq!(cursor_placement(None, &cursor), (cursor.distance, 1.0));
+		let cursor = CursorSettings {
+			snap: false,
+			..Default::default()
+		};
+		assert_eq!(cursor_placement(Some(2.0), &cursor), (cursor.distance, 1.0));
+	}
+
+	#[test]
+	fn cursor_doesnt_collapse_inside_a_field() {
+		let cursor = CursorSettings::default();
+		for hit in [0.0, NEAR_DISTANCE] {
+			assert_eq!(cursor_placement(Some(hit), &cursor), (cursor.distance, 1.0));
+		}
+		assert_eq!(drawable_distance(Some(0.0)), None);
+	}
+}
@@ -0,0 +1,87 @@
+use glam::Vec2;
+use stardust_xr_fusion::Mutex;
+use std::{
+	collections::HashMap,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+};
+use tokio::sync::mpsc;
+
+/// A channel from the pulse callbacks to the frame that never drops an event,
+/// so button and key releases can't go missing and leave them stuck down.
+///
+/// Events sent while `capacity` are already waiting are still queued, but counted as overflows.
+pub fn event_channel<T>(capacity: usize) -> (EventSender<T>, EventReceiver<T>) {
+	let (tx, rx) = mpsc::unbounded_channel();
+	let counts = Arc::new(EventCounts::default());
+	(
+		EventSender {
+			tx,
+			counts: counts.clone(),
+			capacity,
+		},
+		EventReceiver { rx, counts },
+	)
+}
+
+#[derive(Debug, Default)]
+struct EventCounts {
+	queued: AtomicUsize,
+	overflows: AtomicUsize,
+}
+
+pub struct EventSender<T> {
+	tx: mpsc::UnboundedSender<T>,
+	counts: Arc<EventCounts>,
+	capacity: usize,
+}
+impl<T> EventSender<T> {
+	pub fn send(&self, event: T) {
+		if self.tx.send(event).is_err() {
+			return;
+		}
+		if self.counts.queued.fetch_add(1, Ordering::Relaxed) >= self.capacity {
+			self.counts.overflows.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+}
+
+pub struct EventReceiver<T> {
+	rx: mpsc::UnboundedReceiver<T>,
+	counts: Arc<EventCounts>,
+}
+impl<T> EventReceiver<T> {
+	pub fn try_recv(&mut self) -> Option<T> {
+		let event = self.rx.try_recv().ok()?;
+		self.counts.queued.fetch_sub(1, Ordering::Relaxed);
+		Some(event)
+	}
+
+	/// How many events were sent over capacity since the last call
+	pub fn take_overflows(&self) -> usize {
+		self.counts.overflows.swap(0, Ordering::Relaxed)
+	}
+}
+
+/// Mouse movement that hasn't been applied yet, summed per device as it comes in
+/// so any number of moves between frames takes the same space.
+#[derive(Debug, Clone, Default)]
+pub struct PendingMovement(Arc<Mutex<HashMap<String, Vec2>>>);
+impl PendingMovement {
+	pub fn add(&self, device: &str, delta: Vec2) {
+		let mut movement = self.0.lock();
+		match movement.get_mut(device) {
+			Some(movement) => *movement += delta,
+			None => {
+				movement.insert(device.to_string(), delta);
+			}
+		}
+	}
+
+	/// Every device's movement since the last call
+	pub fn take(&self) -> HashMap<String, Vec2> {
+		std::mem::take(&mut *self.0.lock())
+	}
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+/// How keyboard focus moves between keyboard receivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusMode {
+	/// Clicking on something gives it focus, clicking on nothing takes focus away
+	#[default]
+	Click,
+	/// Whatever the pointer is over has focus
+	FollowPointer,
+}
+
+/// Which keyboard receiver gets the keys, by uid
+#[derive(Debug, Default)]
+pub struct KeyboardFocus {
+	focused: Option<String>,
+}
+impl KeyboardFocus {
+	/// `target` is the keyboard receiver under the pointer and `clicked` is whether select was pressed this frame.
+	/// Returns whether the focus changed.
+	pub fn update(&mut self, mode: FocusMode, target: Option<&str>, clicked: bool) -> bool {
+		let follow = match mode {
+			FocusMode::Click => clicked,
+			FocusMode::FollowPointer => true,
+		};
+		if !follow || self.focused.as_deref() == target {
+			return false;
+		}
+		self.focused = target.map(str::to_string);
+		true
+	}
+
+	pub fn focused(&self) -> Option<&str> {
+		self.focused.as_deref()
+	}
+
+	/// Forget the focused receiver if it's `uid`
+	pub fn receiver_dropped(&mut self, uid: &str) {
+		if self.focused.as_deref() == Some(uid) {
+			self.focused = None;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn click_focus_only_moves_on_click() {
+		let mut focus = KeyboardFocus::default();
+		assert!(!focus.update(FocusMode::Click, Some("a"), false));
+		assert_eq!(focus.focused(), None);
+		assert!(focus.update(FocusMode::Click, Some("a"), true));
+		assert_eq!(focus.focused(), Some("a"));
+		assert!(!focus.update(FocusMode::Click, Some("b"), false));
+		assert!(!focus.update(FocusMode::Click, None, false));
+		assert_eq!(focus.focused(), Some("a"));
+	}
+
+	#[test]
+	fn clicking_on_nothing_takes_focus_away() {
+		let mut focus = KeyboardFocus::default();
+		focus.update(FocusMode::Click, Some("a"), true);
+		assert!(focus.update(FocusMode::Click, None, true));
+		assert_eq!(focus.focused(), None);
+	}
+
+	#[test]
+	fn clicking_the_focused_receiver_changes_nothing() {
+		let mut focus = KeyboardFocus::default();
+		focus.update(FocusMode::Click, Some("a"), true);
+		assert!(!focus.update(FocusMode::Click, Some("a"), true));
+	}
+
+	#[test]
+	fn follow_pointer_focus_moves_without_clicking() {
+		let mut focus = KeyboardFocus::default();
+		assert!(focus.update(FocusMode::FollowPointer, Some("a"), false));
+		assert!(focus.update(FocusMode::FollowPointer, Some("b"), false));
+		assert_eq!(focus.focused(), Some("b"));
+		assert!(focus.update(FocusMode::FollowPointer, None, false));
+		assert_eq!(focus.focused(), None);
+	}
+
+	#[test]
+	fn dropping_the_focused_receiver_drops_focus() {
+		let mut focus = KeyboardFocus::default();
+		focus.update(FocusMode::Click, Some("a"), true);
+		focus.receiver_dropped("b");
+		assert_eq!(focus.focused(), Some("a"));
+		focus.receiver_dropped("a");
+		assert_eq!(focus.focused(), None);
+	}
+}
@@ -0,0 +1,609 @@
+use crate::receivers::{EnclosingPolicy, KeyboardReceivers, MouseReceivers};
+use glam::{Quat, Vec3};
+use stardust_xr_fusion::{
+	fields::{Field, RayMarchResult, UnknownField},
+	input::{InputHandler, InputMethod},
+	node::NodeType,
+	spatial::Spatial,
+	Mutex,
+};
+use std::{
+	collections::HashMap,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+use tokio::{
+	sync::{mpsc, watch},
+	task::JoinSet,
+};
+
+/// How long a result is trusted while the pointer and handlers stay the same, since fields can move on their own
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What the pointer ray hit
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointerHit {
+	/// which request this answers, newer requests have higher generations
+	pub generation: u64,
+	/// uids of every input handler the ray hits, in the order they get input
+	pub handlers: Vec<String>,
+	/// how far along the ray the closest input handler was hit
+	pub distance: Option<f32>,
+	/// uid of the closest keyboard receiver under the ray
+	pub keyboard_target: Option<String>,
+	/// uid of the closest mouse receiver under the ray
+	pub mouse_target: Option<String>,
+	/// where the ray hits each input handler that gets input, by uid, in that handler's space.
+	/// Captured handlers the ray misses get where it crosses their XY plane.
+	pub hit_points: HashMap<String, Vec3>,
+}
+
+/// What the frame wants hit tested
+#[derive(Debug, Clone, Default, PartialEq)]
+struct HitRequest {
+	generation: u64,
+	/// handlers hit within this distance of each other are ordered by uid instead
+	tie_epsilon: f32,
+	/// uids of handlers to keep giving input to in this order, no matter what's hit
+	capture: Option<Vec<String>>,
+	/// uids of handlers the ray missed that still get input after the others, until they're told they were left
+	linger: Vec<String>,
+	enclosing: EnclosingPolicy,
+}
+
+/// A single long-lived task that ray-marches the input handlers and keyboard and mouse receivers.
+///
+/// Requests that pile up while it's busy are merged into one, and it only marches again
+/// when the pointer moved or the set of input handlers changed.
+pub struct HitTester {
+	request_tx: watch::Sender<HitRequest>,
+	hit_rx: mpsc::Receiver<PointerHit>,
+	latest: PointerHit,
+}
+impl HitTester {
+	pub fn spawn(
+		pointer: InputMethod,
+		keyboard_receivers: Arc<Mutex<KeyboardReceivers>>,
+		mouse_receivers: Arc<Mutex<MouseReceivers>>,
+	) -> Self {
+		let (request_tx, request_rx) = watch::channel(HitRequest::default());
+		let (hit_tx, hit_rx) = mpsc::channel(8);
+		tokio::task::spawn(hit_test_loop(
+			pointer,
+			keyboard_receivers,
+			mouse_receivers,
+			request_rx,
+			hit_tx,
+		));
+		HitTester {
+			request_tx,
+			hit_rx,
+			latest: PointerHit::default(),
+		}
+	}
+
+	/// Ask for a new hit test, replacing any request that hasn't been started yet.
+	///
+	/// While `capture` is set the handler order is frozen to those handlers,
+	/// everything else is still hit tested as usual. `linger` is added to the end of the order,
+	/// so it should be empty while capturing to keep the order frozen.
+	pub fn request(
+		&self,
+		tie_epsilon: f32,
+		capture: Option<Vec<String>>,
+		linger: Vec<String>,
+		enclosing: EnclosingPolicy,
+	) {
+		self.request_tx.send_modify(|request| {
+			request.generation += 1;
+			request.tie_epsilon = tie_epsilon;
+			request.capture = capture;
+			request.linger = linger;
+			request.enclosing = enclosing;
+		});
+	}
+
+	/// The newest result so far
+	pub fn latest(&mut self) -> PointerHit {
+		while let Ok(hit) = self.hit_rx.try_recv() {
+			if hit.generation >= self.latest.generation {
+				self.latest = hit;
+			}
+		}
+		self.latest.clone()
+	}
+}
+
+/// What the last march was based on
+#[derive(Debug, Clone, PartialEq)]
+struct MarchInputs {
+	position: Vec3,
+	rotation: Quat,
+	handler_uids: Vec<String>,
+	tie_epsilon: f32,
+	capture: Option<Vec<String>>,
+	linger: Vec<String>,
+	enclosing: EnclosingPolicy,
+}
+impl MarchInputs {
+	fn same_as(&self, other: &MarchInputs) -> bool {
+		self.handler_uids == other.handler_uids
+			&& self.tie_epsilon == other.tie_epsilon
+			&& self.capture == other.capture
+			&& self.linger == other.linger
+			&& self.enclosing == other.enclosing
+			&& self.position.distance(other.position) < 0.0001
+			&& self.rotation.angle_between(other.rotation) < 0.0001
+	}
+}
+
+async fn hit_test_loop(
+	pointer: InputMethod,
+	keyboard_receivers: Arc<Mutex<KeyboardReceivers>>,
+	mouse_receivers: Arc<Mutex<MouseReceivers>>,
+	mut request_rx: watch::Receiver<HitRequest>,
+	hit_tx: mpsc::Sender<PointerHit>,
+) {
+	let Ok(client) = pointer.client() else {return};
+	let root = client.get_root().alias();
+	let mut last_inputs: Option<MarchInputs> = None;
+	let mut last_march = Instant::now();
+
+	// ends once the `HitTester` is dropped
+	while request_rx.changed().await.is_ok() {
+		let request = request_rx.borrow_and_update().clone();
+		let Some(inputs) = march_inputs(&pointer, &root, &request).await else {continue};
+		if last_inputs
+			.as_ref()
+			.is_some_and(|last| last.same_as(&inputs))
+			&& last_march.elapsed() < REFRESH_INTERVAL
+		{
+			continue;
+		}
+
+		let Some(hit) = march(
+			&pointer,
+			&keyboard_receivers,
+			&mouse_receivers,
+			&request,
+			&inputs.handler_uids,
+		)
+		.await
+		else {
+			// handlers could have come or gone while marching, so this order is already stale
+			last_inputs = None;
+			continue;
+		};
+		last_inputs.replace(inputs);
+		last_march = Instant::now();
+
+		if hit_tx.send(hit).await.is_err() {
+			return;
+		}
+	}
+}
+
+/// Ray-march every input handler and receiver and set the handler order, or `None` if the handlers
+/// changed from `handler_uids_before` while marching
+#[tracing::instrument(level = "debug", skip_all, fields(generation = request.generation))]
+async fn march(
+	pointer: &InputMethod,
+	keyboard_receivers: &Mutex<KeyboardReceivers>,
+	mouse_receivers: &Mutex<MouseReceivers>,
+	request: &HitRequest,
+	handler_uids_before: &[String],
+) -> Option<PointerHit> {
+	let hits = hit_handlers(pointer).await;
+	if handler_uids(pointer) != handler_uids_before {
+		tracing::debug!("Input handlers changed while marching");
+		return None;
+	}
+	let distance = hits
+		.iter()
+		.map(|(_, _, distance)| *distance)
+		.min_by(f32::total_cmp);
+	let hits = hits
+		.into_iter()
+		.map(|(uid, handler, distance)| (uid, (handler, distance), distance))
+		.collect();
+	let ordered = order_by_distance(hits, request.tie_epsilon);
+	let handler_uids: Vec<_> = ordered.iter().map(|(uid, _)| uid.clone()).collect();
+	let order = handler_order(
+		request.capture.as_ref().unwrap_or(&handler_uids),
+		&request.linger,
+	);
+	{
+		let input_handlers = pointer.input_handlers();
+		let order = order
+			.iter()
+			.filter_map(|uid| input_handlers.get(*uid))
+			.collect::<Vec<_>>();
+		warn_err!(pointer.set_handler_order(&order), "set the handler order");
+	}
+
+	// everything else is only read by the frame, so it doesn't hold up the handler order
+	let hit_point_targets: Vec<_> = match &request.capture {
+		Some(capture) => {
+			let input_handlers = pointer.input_handlers();
+			capture
+				.iter()
+				.filter_map(|uid| {
+					let handler = input_handlers.get(uid)?.alias();
+					let distance = ordered
+						.iter()
+						.find(|(hit_uid, _)| hit_uid == uid)
+						.map(|(_, (_, distance))| *distance);
+					Some((uid.clone(), handler, distance))
+				})
+				.collect()
+		}
+		None => ordered
+			.into_iter()
+			.map(|(uid, (handler, distance))| (uid, handler, Some(distance)))
+			.collect(),
+	};
+	let keyboard_targets = keyboard_receivers.lock().receivers.targets();
+	let mouse_targets = mouse_receivers.lock().receivers.targets();
+	let (keyboard_target, mouse_target, hit_points) = tokio::join!(
+		closest_receiver(pointer, keyboard_targets, request.enclosing),
+		closest_receiver(pointer, mouse_targets, request.enclosing),
+		hit_points(pointer, hit_point_targets),
+	);
+	let hit = PointerHit {
+		generation: request.generation,
+		handlers: handler_uids,
+		distance,
+		keyboard_target,
+		mouse_target,
+		hit_points,
+	};
+	tracing::debug!(
+		handlers = ?hit.handlers,
+		distance = ?hit.distance,
+		keyboard_target = ?hit.keyboard_target,
+		mouse_target = ?hit.mouse_target,
+		"Hit test done"
+	);
+	Some(hit)
+}
+
+async fn march_inputs(
+	pointer: &InputMethod,
+	root: &Spatial,
+	request: &HitRequest,
+) -> Option<MarchInputs> {
+	let (position, rotation, _scale) =
+		pointer.get_position_rotation_scale(root).ok()?.await.ok()?;
+	Some(MarchInputs {
+		position: position.into(),
+		rotation: rotation.into(),
+		handler_uids: handler_uids(pointer),
+		tie_epsilon: request.tie_epsilon,
+		capture: request.capture.clone(),
+		linger: request.linger.clone(),
+		enclosing: request.enclosing,
+	})
+}
+
+fn handler_uids(pointer: &InputMethod) -> Vec<String> {
+	let mut uids = pointer.input_handlers().keys().cloned().collect::<Vec<_>>();
+	uids.sort();
+	uids
+}
+
+/// Every handler the ray hits as `(uid, handler, distance along the ray)`
+async fn hit_handlers(pointer: &InputMethod) -> Vec<(String, InputHandler, f32)> {
+	let mut join = JoinSet::new();
+	for (uid, handler) in pointer.input_handlers().iter() {
+		let Some(field) = handler.field() else {continue};
+		let Ok(ray_march_result) = field.ray_march(pointer, [0.0; 3], [0.0, 0.0, -1.0]) else {continue};
+		let uid = uid.clone();
+		let handler = handler.alias();
+		join.spawn(async move { (uid, handler, ray_march_result.await) });
+	}
+
+	let mut marches = Vec::new();
+	while let Some(res) = join.join_next().await {
+		let Ok((uid, handler, Ok(ray_info))) = res else {continue};
+		marches.push((uid, handler, ray_info));
+	}
+	hits(marches)
+}
+
+/// The marches out of `(uid, T, ray march)` that hit, as `(uid, T, distance along the ray)`
+pub fn hits<T>(
+	marches: impl IntoIterator<Item = (String, T, RayMarchResult)>,
+) -> Vec<(String, T, f32)> {
+	marches
+		.into_iter()
+		.filter(|(_, _, ray_info)| ray_info.hit())
+		.map(|(uid, hit, ray_info)| (uid, hit, ray_info.deepest_point_distance))
+		.collect()
+}
+
+/// Where the ray hits each of `targets` of `(uid, handler, distance along the ray)`, in that handler's space
+async fn hit_points(
+	pointer: &InputMethod,
+	targets: Vec<(String, InputHandler, Option<f32>)>,
+) -> HashMap<String, Vec3> {
+	let mut join = JoinSet::new();
+	for (uid, handler, distance) in targets {
+		let Ok(transform) = handler.get_position_rotation_scale(pointer) else {continue};
+		join.spawn(async move { (uid, distance, transform.await) });
+	}
+
+	let mut points = HashMap::new();
+	while let Some(res) = join.join_next().await {
+		let Ok((uid, distance, Ok((position, rotation, scale)))) = res else {continue};
+		let Some(point) = hit_point(position.into(), rotation.into(), scale.into(), distance) else {continue};
+		points.insert(uid, point);
+	}
+	points
+}
+
+/// Where the ray lands in the space of something at `position`, `rotation` and `scale` relative to the pointer,
+/// `distance` along it or where it crosses that space's XY plane without one
+pub fn hit_point(
+	position: Vec3,
+	rotation: Quat,
+	scale: Vec3,
+	distance: Option<f32>,
+) -> Option<Vec3> {
+	let to_local = |point: Vec3| rotation.inverse() * (point - position) / scale;
+	let origin = to_local(Vec3::ZERO);
+	let direction = to_local(Vec3::NEG_Z) - origin;
+	let distance = match distance {
+		Some(distance) => distance,
+		None => Some(-origin.z / direction.z)
+			.filter(|distance| *distance >= 0.0 && distance.is_finite())?,
+	};
+	Some(origin + direction * distance)
+}
+
+/// Order hits closest first so handlers behind the front one can get input too.
+///
+/// Hits within `tie_epsilon` of the start of a run of near-equal distances are ordered by uid,
+/// so which of them comes first doesn't flicker with tiny distance changes.
+pub fn order_by_distance<T>(mut hits: Vec<(String, T, f32)>, tie_epsilon: f32) -> Vec<(String, T)> {
+	hits.sort_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+	let mut ordered = Vec::with_capacity(hits.len());
+	let mut hits = hits.into_iter().peekable();
+	while let Some(first) = hits.next() {
+		let mut group = vec![first];
+		while let Some(next) =
+			hits.next_if(|(_, _, distance)| *distance - group[0].2 <= tie_epsilon)
+		{
+			group.push(next);
+		}
+		group.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+		ordered.extend(group.into_iter().map(|(uid, hit, _)| (uid, hit)));
+	}
+	ordered
+}
+
+/// The uids of the handlers that get input, in order: `first`, then any of `linger` that aren't in it already
+pub fn handler_order<'a>(first: &'a [String], linger: &'a [String]) -> Vec<&'a String> {
+	let lingering = linger.iter().filter(|uid| !first.contains(uid));
+	first.iter().chain(lingering).collect()
+}
+
+/// The uid of the closest pulse receiver the ray hits out of `targets`
+async fn closest_receiver(
+	pointer: &InputMethod,
+	targets: Vec<(String, UnknownField)>,
+	enclosing: EnclosingPolicy,
+) -> Option<String> {
+	let mut join = JoinSet::new();
+	for (uid, field) in targets {
+		let Ok(ray_march_result) = field.ray_march(pointer, [0.0; 3], [0.0, 0.0, -1.0]) else {continue};
+		let Ok(pointer_distance) = field.distance(pointer, [0.0; 3]) else {continue};
+		join.spawn(async move { (uid, ray_march_result.await, pointer_distance.await) });
+	}
+
+	let mut marches = Vec::new();
+	while let Some(res) = join.join_next().await {
+		let Ok((uid, Ok(ray_info), Ok(pointer_distance))) = res else {continue};
+		marches.push((uid, ray_info, pointer_distance));
+	}
+	closest_receiver_uid(marches, enclosing)
+}
+
+/// The uid of the closest receiver the ray hits out of `(uid, ray march, distance from the pointer to the field)`.
+///
+/// Equally close receivers are picked by uid, so the choice doesn't depend on which march finished first.
+pub fn closest_receiver_uid(
+	marches: impl IntoIterator<Item = (String, RayMarchResult, f32)>,
+	enclosing: EnclosingPolicy,
+) -> Option<String> {
+	marches
+		.into_iter()
+		.filter(|(_, ray_info, _)| ray_info.hit())
+		.filter_map(|(uid, ray_info, pointer_distance)| {
+			// inside the field the distance to it is negative, so enclosing receivers sort before any the ray enters
+			let distance = match (pointer_distance <= 0.0, enclosing) {
+				(true, EnclosingPolicy::Ignore) => return None,
+				(true, EnclosingPolicy::Target) => pointer_distance,
+				(false, _) => ray_info.deepest_point_distance,
+			};
+			Some((uid, distance))
+		})
+		.min_by(|(uid_a, a), (uid_b, b)| a.total_cmp(b).then_with(|| uid_a.cmp(uid_b)))
+		.map(|(uid, _)| uid)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn march(distance: f32, hit: bool) -> RayMarchResult {
+		RayMarchResult {
+			ray_origin: [0.0; 3].into(),
+			ray_direction: [0.0, 0.0, -1.0].into(),
+			min_distance: if hit { -0.01 } else { 0.1 },
+			deepest_point_distance: distance,
+			ray_length: 10.0,
+			ray_steps: 8,
+		}
+	}
+
+	fn uids<T>(ordered: &[(String, T)]) -> Vec<&str> {
+		ordered.iter().map(|(uid, _)| uid.as_str()).collect()
+	}
+
+	#[test]
+	fn closest_hit_comes_first() {
+		let hits = vec![
+			("far".into(), (), 2.0),
+			("near".into(), (), 0.5),
+			("mid".into(), (), 1.0),
+		];
+		assert_eq!(
+			uids(&order_by_distance(hits, 0.001)),
+			["near", "mid", "far"]
+		);
+	}
+
+	#[test]
+	fn ties_are_ordered_by_uid() {
+		let hits = vec![
+			("b".into(), (), 1.0),
+			("a".into(), (), 1.0005),
+			("c".into(), (), 0.5),
+		];
+		assert_eq!(uids(&order_by_distance(hits, 0.001)), ["c", "a", "b"]);
+	}
+
+	#[test]
+	fn ties_are_measured_from_the_closest_in_the_group() {
+		// "c" is within epsilon of "b" but not of "a", so it starts a new group
+		let hits = vec![
+			("c".into(), (), 1.0015),
+			("b".into(), (), 1.001),
+			("a".into(), (), 1.0),
+		];
+		assert_eq!(uids(&order_by_distance(hits, 0.0012)), ["a", "b", "c"]);
+	}
+
+	#[test]
+	fn no_hits_order_to_nothing() {
+		assert!(order_by_distance(Vec::<(String, (), f32)>::new(), 0.001).is_empty());
+	}
+
+	#[test]
+	fn lingering_handlers_come_last_once() {
+		let first = vec!["b".to_string(), "a".to_string()];
+		let linger = vec!["a".to_string(), "c".to_string()];
+		assert_eq!(handler_order(&first, &linger), ["b", "a", "c"]);
+	}
+
+	#[test]
+	fn hit_point_is_along_the_ray_in_local_space() {
+		let point = hit_point(
+			Vec3::new(1.0, 0.0, -2.0),
+			Quat::IDENTITY,
+			Vec3::ONE,
+			Some(2.0),
+		);
+		assert_eq!(point, Some(Vec3::new(-1.0, 0.0, 0.0)));
+		let scaled = hit_point(
+			Vec3::new(0.0, 0.0, -2.0),
+			Quat::IDENTITY,
+			Vec3::splat(2.0),
+			Some(1.0),
+		);
+		assert_eq!(scaled, Some(Vec3::new(0.0, 0.0, 0.5)));
+	}
+
+	#[test]
+	fn hit_point_follows_rotation() {
+		// turned to face the pointer, so its +x is the pointer's -x
+		let rotation = Quat::from_rotation_y(std::f32::consts::PI);
+		let point = hit_point(Vec3::new(0.5, 0.0, -2.0), rotation, Vec3::ONE, Some(2.0)).unwrap();
+		assert!(
+			point.abs_diff_eq(Vec3::new(0.5, 0.0, 0.0), 0.0001),
+			"{point}"
+		);
+	}
+
+	#[test]
+	fn missed_hit_point_lands_on_the_xy_plane() {
+		let point = hit_point(Vec3::new(0.3, 0.2, -1.0), Quat::IDENTITY, Vec3::ONE, None);
+		assert_eq!(point, Some(Vec3::new(-0.3, -0.2, 0.0)));
+	}
+
+	#[test]
+	fn xy_plane_behind_the_pointer_isnt_hit() {
+		assert_eq!(
+			hit_point(Vec3::new(0.0, 0.0, 1.0), Quat::IDENTITY, Vec3::ONE, None),
+			None
+		);
+	}
+
+	#[test]
+	fn misses_are_not_hits() {
+		let marches = vec![
+			("hit".into(), 1, march(1.0, true)),
+			("miss".into(), 2, march(0.5, false)),
+		];
+		assert_eq!(hits(marches), [("hit".to_string(), 1, 1.0)]);
+	}
+
+	#[test]
+	fn closest_receiver_skips_misses() {
+		let marches = vec![
+			("miss".into(), march(0.5, false), 0.5),
+			("hit".into(), march(1.0, true), 1.0),
+		];
+		assert_eq!(
+			closest_receiver_uid(marches, EnclosingPolicy::Ignore).as_deref(),
+			Some("hit")
+		);
+	}
+
+	#[test]
+	fn closest_receiver_wins() {
+		let marches = vec![
+			("far".into(), march(2.0, true), 2.0),
+			("near".into(), march(1.0, true), 1.0),
+		];
+		assert_eq!(
+			closest_receiver_uid(marches, EnclosingPolicy::Ignore).as_deref(),
+			Some("near")
+		);
+	}
+
+	#[test]
+	fn equally_close_receivers_are_picked_by_uid() {
+		let marches = vec![
+			("b".into(), march(1.0, true), 1.0),
+			("a".into(), march(1.0, true), 1.0),
+		];
+		assert_eq!(
+			closest_receiver_uid(marches, EnclosingPolicy::Ignore).as_deref(),
+			Some("a")
+		);
+	}
+
+	#[test]
+	fn enclosing_receivers_follow_the_policy() {
+		let marches = || {
+			vec![
+				("outside".into(), march(1.0, true), 1.0),
+				("around".into(), march(0.0, true), -0.5),
+			]
+		};
+		assert_eq!(
+			closest_receiver_uid(marches(), EnclosingPolicy::Ignore).as_deref(),
+			Some("outside")
+		);
+		assert_eq!(
+			closest_receiver_uid(marches(), EnclosingPolicy::Target).as_deref(),
+			Some("around")
+		);
+	}
+
+	#[test]
+	fn no_receivers_means_no_target() {
+		assert_eq!(closest_receiver_uid([], EnclosingPolicy::Target), None);
+	}
+}
@@ -0,0 +1,66 @@
+use crate::modifiers::ModifierState;
+use serde::{Deserialize, Serialize};
+use stardust_xr_molecules::keyboard::xkb;
+use std::fmt::Display;
+
+/// A key along with the modifiers that have to be held with it, written like `Super+Shift+R`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+	pub modifiers: ModifierState,
+	pub keysym: xkb::Keysym,
+}
+impl Hotkey {
+	pub fn matches(&self, keysym: xkb::Keysym, modifiers: ModifierState) -> bool {
+		self.keysym == keysym && self.modifiers == modifiers
+	}
+}
+impl TryFrom<String> for Hotkey {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		let mut modifiers = ModifierState::default();
+		let mut parts = value.split('+').map(str::trim).collect::<Vec<_>>();
+		let key = parts.pop().unwrap_or_default();
+		for modifier in parts {
+			match modifier.to_lowercase().as_str() {
+				"shift" => modifiers.shift = true,
+				"ctrl" | "control" => modifiers.ctrl = true,
+				"alt" => modifiers.alt = true,
+				"super" | "logo" => modifiers.super_key = true,
+				_ => return Err(format!("Unknown modifier {modifier} in hotkey {value}")),
+			}
+		}
+		let keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+		if keysym == xkb::keysyms::KEY_NoSymbol {
+			return Err(format!("Unknown key {key} in hotkey {value}"));
+		}
+		Ok(Hotkey { modifiers, keysym })
+	}
+}
+impl From<Hotkey> for String {
+	fn from(hotkey: Hotkey) -> Self {
+		hotkey.to_string()
+	}
+}
+impl Display for Hotkey {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let ModifierState {
+			shift,
+			ctrl,
+			alt,
+			super_key,
+		} = self.modifiers;
+		for (held, name) in [
+			(super_key, "Super"),
+			(ctrl, "Ctrl"),
+			(alt, "Alt"),
+			(shift, "Shift"),
+		] {
+			if held {
+				write!(f, "{name}+")?;
+			}
+		}
+		write!(f, "{}", xkb::keysym_get_name(self.keysym))
+	}
+}
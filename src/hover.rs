@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+/// Input handlers that started or stopped being hovered this frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HoverChanges {
+	pub entered: Vec<String>,
+	pub left: Vec<String>,
+}
+
+/// Remembers which input handlers the pointer is over between frames.
+///
+/// A handler only counts as left once it's been missed for the leave delay,
+/// so skimming along the edge of a field doesn't make it flicker in and out.
+/// While a button is held only the captured handlers can be entered or left,
+/// since nothing else is in the handler order to be told.
+#[derive(Debug, Default)]
+pub struct HoverTracker {
+	/// uid of each hovered handler and when it was last hit
+	hovered: HashMap<String, f64>,
+	/// uids of the handlers left in the last update
+	left: Vec<String>,
+}
+impl HoverTracker {
+	/// `hit` is the uids of every handler the ray hits right now, `capture` the handlers captured while a button is held,
+	/// `now` is in seconds
+	pub fn update(
+		&mut self,
+		hit: &[String],
+		capture: Option<&[String]>,
+		now: f64,
+		leave_delay: f64,
+	) -> HoverChanges {
+		let captured = |uid: &String| match capture {
+			Some(capture) => capture.contains(uid),
+			None => true,
+		};
+		let mut changes = HoverChanges::default();
+		for uid in hit.iter().filter(|uid| captured(uid)) {
+			if self.hovered.insert(uid.clone(), now).is_none() {
+				changes.entered.push(uid.clone());
+			}
+		}
+		self.hovered.retain(|uid, last_hit| {
+			let hovered = !captured(uid) || now - *last_hit < leave_delay || hit.contains(uid);
+			if !hovered {
+				changes.left.push(uid.clone());
+			}
+			hovered
+		});
+		self.left.clone_from(&changes.left);
+		changes
+	}
+
+	/// Handlers the ray misses that still need input, so they get every frame up to and including
+	/// the one that says they were left. `hit` is the same as in the last update.
+	pub fn lingering(&self, hit: &[String]) -> Vec<String> {
+		let mut lingering: Vec<_> = self
+			.hovered
+			.keys()
+			.filter(|uid| !hit.contains(uid))
+			.chain(&self.left)
+			.cloned()
+			.collect();
+		lingering.sort();
+		lingering
+	}
+
+	pub fn is_hovering(&self) -> bool {
+		!self.hovered.is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DELAY: f64 = 0.1;
+
+	fn uids(uids: &[&str]) -> Vec<String> {
+		uids.iter().map(|uid| uid.to_string()).collect()
+	}
+
+	#[test]
+	fn hitting_a_handler_enters_it_once() {
+		let mut hover = HoverTracker::default();
+		let changes = hover.update(&uids(&["a"]), None, 0.0, DELAY);
+		assert_eq!(changes.entered, uids(&["a"]));
+		assert!(hover.is_hovering());
+		assert_eq!(
+			hover.update(&uids(&["a"]), None, 0.016, DELAY),
+			HoverChanges::default()
+		);
+	}
+
+	#[test]
+	fn missing_a_handler_leaves_it_after_the_delay() {
+		let mut hover = HoverTracker::default();
+		hover.update(&uids(&["a"]), None, 0.0, DELAY);
+		assert_eq!(
+			hover.update(&[], None, 0.05, DELAY),
+			HoverChanges::default()
+		);
+		assert!(hover.is_hovering());
+		let changes = hover.update(&[], None, 0.1, DELAY);
+		assert_eq!(changes.left, uids(&["a"]));
+		assert!(!hover.is_hovering());
+		assert_eq!(hover.update(&[], None, 0.2, DELAY), HoverChanges::default());
+	}
+
+	#[test]
+	fn hitting_again_during_the_delay_stays_hovered() {
+		let mut hover = HoverTracker::default();
+		hover.update(&uids(&["a"]), None, 0.0, DELAY);
+		hover.update(&[], None, 0.05, DELAY);
+		assert_eq!(
+			hover.update(&uids(&["a"]), None, 0.08, DELAY),
+			HoverChanges::default()
+		);
+		// the delay starts over from the last hit
+		assert_eq!(
+			hover.update(&[], None, 0.15, DELAY),
+			HoverChanges::default()
+		);
+		assert_eq!(hover.update(&[], None, 0.2, DELAY).left, uids(&["a"]));
+	}
+
+	#[test]
+	fn leaving_handlers_linger_until_told() {
+		let mut hover = HoverTracker::default();
+		hover.update(&uids(&["a", "b"]), None, 0.0, DELAY);
+		assert!(hover.lingering(&uids(&["a", "b"])).is_empty());
+		hover.update(&uids(&["b"]), None, 0.05, DELAY);
+		assert_eq!(hover.lingering(&uids(&["b"])), uids(&["a"]));
+		// the frame that says "a" was left still gives it input
+		hover.update(&uids(&["b"]), None, 0.1, DELAY);
+		assert_eq!(hover.lingering(&uids(&["b"])), uids(&["a"]));
+		hover.update(&uids(&["b"]), None, 0.15, DELAY);
+		assert!(hover.lingering(&uids(&["b"])).is_empty());
+	}
+
+	#[test]
+	fn dragging_across_handlers_only_tracks_the_captured_ones() {
+		let mut hover = HoverTracker::default();
+		hover.update(&uids(&["a", "c"]), None, 0.0, DELAY);
+		hover.update(&uids(&["a"]), None, 0.05, DELAY);
+		let capture = uids(&["a"]);
+		// dragging off "a" onto "b", neither "b" entering nor "c" leaving is reported
+		assert_eq!(
+			hover.update(&uids(&["b"]), Some(&capture), 0.1, DELAY),
+			HoverChanges::default()
+		);
+		assert_eq!(
+			hover.update(&uids(&["b"]), Some(&capture), 0.2, DELAY).left,
+			uids(&["a"])
+		);
+		assert_eq!(
+			hover.update(&uids(&["b"]), Some(&capture), 0.3, DELAY),
+			HoverChanges::default()
+		);
+		// once the button is let go both catch up
+		let changes = hover.update(&uids(&["b"]), None, 0.35, DELAY);
+		assert_eq!(changes.entered, uids(&["b"]));
+		assert_eq!(changes.left, uids(&["c"]));
+		assert_eq!(hover.lingering(&uids(&["b"])), uids(&["c"]));
+	}
+}
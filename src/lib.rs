@@ -0,0 +1,18 @@
+#[macro_use]
+mod rate_limit;
+
+pub mod acceleration;
+pub mod cursor;
+pub mod events;
+pub mod focus;
+pub mod hit_test;
+pub mod hotkey;
+pub mod hover;
+pub mod modifiers;
+pub mod pointer;
+pub mod receivers;
+pub mod session;
+pub mod settings;
+
+pub use pointer::{Azimuth, Datamap};
+pub use session::{stay_connected, Resume, Session};
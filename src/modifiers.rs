@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use stardust_xr_molecules::keyboard::{xkb, KeyboardEvent};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+	Shift,
+	Ctrl,
+	Alt,
+	Super,
+}
+impl Modifier {
+	fn from_keysym(keysym: xkb::Keysym) -> Option<Self> {
+		match keysym {
+			xkb::keysyms::KEY_Shift_L | xkb::keysyms::KEY_Shift_R => Some(Modifier::Shift),
+			xkb::keysyms::KEY_Control_L | xkb::keysyms::KEY_Control_R => Some(Modifier::Ctrl),
+			xkb::keysyms::KEY_Alt_L | xkb::keysyms::KEY_Alt_R => Some(Modifier::Alt),
+			xkb::keysyms::KEY_Super_L | xkb::keysyms::KEY_Super_R => Some(Modifier::Super),
+			_ => None,
+		}
+	}
+}
+
+/// Which modifiers are held, as sent in the pointer datamap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ModifierState {
+	pub shift: bool,
+	pub ctrl: bool,
+	pub alt: bool,
+	#[serde(rename = "super")]
+	pub super_key: bool,
+}
+
+/// Follows the keyboard pulses to know which modifier keys are held.
+///
+/// Keycodes are only meaningful relative to the keymap they were sent with,
+/// so keys are looked up in the last keymap seen.
+#[derive(Debug, Default)]
+pub struct ModifierTracker {
+	keysyms: HashMap<u32, xkb::Keysym>,
+	modifier_keys: HashMap<u32, Modifier>,
+	held: HashSet<u32>,
+}
+impl ModifierTracker {
+	pub fn update(&mut self, event: &KeyboardEvent) {
+		if let Some(keymap) = &event.keymap {
+			self.set_keymap(keymap);
+		}
+		for key in event.keys_up.iter().flatten() {
+			self.held.remove(key);
+		}
+		for key in event.keys_down.iter().flatten() {
+			if self.modifier_keys.contains_key(key) {
+				self.held.insert(*key);
+			}
+		}
+	}
+
+	fn set_keymap(&mut self, keymap: &str) {
+		let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+		let Some(keymap) = xkb::Keymap::new_from_string(
+			&context,
+			keymap.to_string(),
+			xkb::KEYMAP_FORMAT_TEXT_V1,
+			xkb::KEYMAP_COMPILE_NO_FLAGS,
+		) else {return};
+
+		self.keysyms = (keymap.min_keycode()..=keymap.max_keycode())
+			.filter_map(|keycode| {
+				let keysym = keymap.key_get_syms_by_level(keycode, 0, 0).first()?;
+				Some((keycode, *keysym))
+			})
+			.collect();
+		self.modifier_keys = self
+			.keysyms
+			.iter()
+			.filter_map(|(keycode, keysym)| Some((*keycode, Modifier::from_keysym(*keysym)?)))
+			.collect();
+		let modifier_keys = &self.modifier_keys;
+		self.held.retain(|key| modifier_keys.contains_key(key));
+	}
+
+	/// The unshifted keysym of `key` in the current keymap
+	pub fn keysym(&self, key: u32) -> Option<xkb::Keysym> {
+		self.keysyms.get(&key).copied()
+	}
+
+	pub fn is_held(&self, modifier: Modifier) -> bool {
+		self.held
+			.iter()
+			.any(|key| self.modifier_keys.get(key) == Some(&modifier))
+	}
+
+	/// Keycodes of the modifier keys that are held
+	pub fn held_keys(&self) -> Vec<u32> {
+		let mut keys = self.held.iter().copied().collect::<Vec<_>>();
+		keys.sort();
+		keys
+	}
+
+	pub fn state(&self) -> ModifierState {
+		ModifierState {
+			shift: self.is_held(Modifier::Shift),
+			ctrl: self.is_held(Modifier::Ctrl),
+			alt: self.is_held(Modifier::Alt),
+			super_key: self.is_held(Modifier::Super),
+		}
+	}
+}
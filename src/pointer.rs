@@ -0,0 +1,744 @@
+use crate::{
+	cursor::{cursor_placement, drawable_distance, ray_points, CursorState},
+	events::{EventReceiver, PendingMovement},
+	hit_test::{HitTester, PointerHit},
+	hover::HoverTracker,
+	modifiers::{ModifierState, ModifierTracker},
+	receivers::{KeyboardReceivers, MouseReceivers},
+	session::Resume,
+	settings::Settings,
+};
+use color_eyre::Result;
+use glam::{Quat, Vec2};
+use input_event_codes::{
+	BTN_BACK, BTN_EXTRA, BTN_FORWARD, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT, BTN_SIDE,
+};
+use mint::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+use stardust_xr_fusion::{
+	client::{Client, FrameInfo, RootHandler},
+	core::{schemas::flex::flexbuffers, values::Transform},
+	data::PulseSender,
+	drawable::Lines,
+	input::PointerInputMethod,
+	node::NodeType,
+	HandlerWrapper,
+};
+use stardust_xr_molecules::{
+	keyboard::{KeyboardEvent, KEYBOARD_MASK},
+	mouse::{MouseEvent as MouseReceiverEvent, MOUSE_MASK},
+};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::watch;
+
+/// Mouse input for the pointer, from the pulses its mouse receiver gets
+#[derive(Debug)]
+pub enum MouseEvent {
+	LeftClick(bool),
+	RightClick(bool),
+	MiddleClick(bool),
+	BackClick(bool),
+	ForwardClick(bool),
+	Scroll { x: f32, y: f32 },
+	ScrollDiscrete { x: f32, y: f32 },
+	Forward(MouseReceiverEvent),
+}
+impl MouseEvent {
+	/// The event for a linux input event code button going down or up
+	pub fn button(button: u32, pressed: bool) -> Option<Self> {
+		match button {
+			BTN_LEFT!() => Some(MouseEvent::LeftClick(pressed)),
+			BTN_RIGHT!() => Some(MouseEvent::RightClick(pressed)),
+			BTN_MIDDLE!() => Some(MouseEvent::MiddleClick(pressed)),
+			BTN_SIDE!() | BTN_BACK!() => Some(MouseEvent::BackClick(pressed)),
+			BTN_EXTRA!() | BTN_FORWARD!() => Some(MouseEvent::ForwardClick(pressed)),
+			_ => None,
+		}
+	}
+
+	/// Which of the datamap's buttons this is for, if it's a button event
+	fn button_index(&self) -> Option<usize> {
+		match self {
+			MouseEvent::LeftClick(_) => Some(0),
+			MouseEvent::RightClick(_) => Some(1),
+			MouseEvent::MiddleClick(_) => Some(2),
+			MouseEvent::BackClick(_) => Some(3),
+			MouseEvent::ForwardClick(_) => Some(4),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Datamap {
+	pub select: f32,
+	pub grab: f32,
+	pub middle: f32,
+	pub back: f32,
+	pub forward: f32,
+	/// all scrolling this frame, continuous and discrete added together, for handlers that don't tell them apart
+	pub scroll: Vector2<f32>,
+	/// scroll distance this frame, e.g. from touchpads
+	pub scroll_continuous: Vector2<f32>,
+	/// scroll wheel steps this frame
+	pub scroll_discrete: Vector2<f32>,
+	#[serde(flatten)]
+	pub modifiers: ModifierState,
+	/// uids of input handlers the pointer started hovering this frame
+	pub hover_entered: Vec<String>,
+	/// uids of input handlers the pointer stopped hovering this frame
+	pub hover_left: Vec<String>,
+	/// where the ray hits each input handler that gets input, by uid, in that handler's space.
+	/// While a button is held, captured handlers the ray misses get where it crosses their XY plane.
+	pub hit_points: HashMap<String, Vector3<f32>>,
+}
+impl Default for Datamap {
+	fn default() -> Self {
+		Datamap {
+			select: 0.0,
+			grab: 0.0,
+			middle: 0.0,
+			back: 0.0,
+			forward: 0.0,
+			scroll: [0.0; 2].into(),
+			scroll_continuous: [0.0; 2].into(),
+			scroll_discrete: [0.0; 2].into(),
+			modifiers: ModifierState::default(),
+			hover_entered: Vec::new(),
+			hover_left: Vec::new(),
+			hit_points: HashMap::new(),
+		}
+	}
+}
+impl Datamap {
+	pub fn any_button_held(&self) -> bool {
+		[self.select, self.grab, self.middle, self.back, self.forward]
+			.into_iter()
+			.any(|button| button > 0.0)
+	}
+
+	pub fn serialize_pulse_data(&self) -> Vec<u8> {
+		let mut serializer = flexbuffers::FlexbufferSerializer::new();
+		warn_err!(self.serialize(&mut serializer), "serialize the datamap");
+		serializer.take_buffer()
+	}
+}
+
+/// What a frame's worth of mouse events add up to, besides movement
+#[derive(Debug, Default)]
+pub struct MouseInput {
+	/// whether select went down
+	pub clicked: bool,
+	pub scroll_continuous: Vec2,
+	pub scroll_discrete: Vec2,
+	/// events to pass on to the mouse receiver under the pointer
+	pub forwarded: Vec<MouseReceiverEvent>,
+	/// button events held back for the next frame, put these before its events
+	pub deferred: Vec<MouseEvent>,
+}
+impl MouseInput {
+	/// Set the buttons in `datamap` from `events` in order, and its scrolling to the sum of theirs.
+	///
+	/// Each button changes at most once a frame and anything after that for it is deferred to the next,
+	/// so input handlers see every press and release even when a click is quicker than a frame.
+	pub fn collect(datamap: &mut Datamap, events: impl IntoIterator<Item = MouseEvent>) -> Self {
+		let button = |pressed: bool| if pressed { 1.0 } else { 0.0 };
+		let mut input = MouseInput::default();
+		let mut changed = [false; 5];
+		for event in events {
+			if let Some(index) = event.button_index() {
+				if changed[index] {
+					input.deferred.push(event);
+					continue;
+				}
+				changed[index] = true;
+			}
+			match event {
+				MouseEvent::LeftClick(c) => {
+					input.clicked |= c;
+					datamap.select = button(c);
+				}
+				MouseEvent::RightClick(c) => datamap.grab = button(c),
+				MouseEvent::MiddleClick(c) => datamap.middle = button(c),
+				MouseEvent::BackClick(c) => datamap.back = button(c),
+				MouseEvent::ForwardClick(c) => datamap.forward = button(c),
+				MouseEvent::Scroll { x, y } => input.scroll_continuous += Vec2::new(x, y),
+				MouseEvent::ScrollDiscrete { x, y } => input.scroll_discrete += Vec2::new(x, y),
+				MouseEvent::Forward(mouse_event) => input.forwarded.push(mouse_event),
+			}
+		}
+		datamap.scroll = (input.scroll_continuous + input.scroll_discrete).into();
+		datamap.scroll_continuous = input.scroll_continuous.into();
+		datamap.scroll_discrete = input.scroll_discrete.into();
+		input
+	}
+}
+
+/// `yaw` and `pitch` in degrees after the mouse moved `delta` pixels, with `gain` from acceleration
+pub fn turn(yaw: f32, pitch: f32, delta: Vec2, gain: f32, settings: &Settings) -> (f32, f32) {
+	let mut delta = delta * settings.sensitivity * gain;
+	if settings.invert_y {
+		delta.y = -delta.y;
+	}
+	(yaw + delta.x, clamp_pitch(pitch + delta.y, settings))
+}
+
+/// Keep `pitch` within the settings' limits, `max_pitch` winning if they overlap
+pub fn clamp_pitch(pitch: f32, settings: &Settings) -> f32 {
+	pitch.max(settings.min_pitch).min(settings.max_pitch)
+}
+
+/// The pointer, turned by mouse movement and sending input to whatever it points at
+pub struct Azimuth {
+	settings: Settings,
+	settings_rx: Option<watch::Receiver<Settings>>,
+	known_devices: HashSet<String>,
+	pointer: PointerInputMethod,
+	pending_movement: PendingMovement,
+	mouse_event_rx: EventReceiver<MouseEvent>,
+	/// button events from last frame's [`MouseInput::deferred`]
+	deferred_mouse_events: Vec<MouseEvent>,
+	keyboard_event_rx: EventReceiver<KeyboardEvent>,
+	modifiers: ModifierTracker,
+	keyboard_pulse_sender: HandlerWrapper<PulseSender, KeyboardReceivers>,
+	mouse_pulse_sender: HandlerWrapper<PulseSender, MouseReceivers>,
+	lines: Lines,
+	cursor_state: CursorState,
+	ray: Lines,
+	ray_visible: bool,
+	/// hotkeys that were pressed, so their releases aren't sent on either
+	swallowed_keys: HashSet<u32>,
+	hit_tester: HitTester,
+	hit: PointerHit,
+	hover: HoverTracker,
+	/// the handlers under the pointer when a button went down, they get all input until every button is released
+	capture: Option<Vec<String>>,
+	yaw: f32,
+	pitch: f32,
+	datamap: Datamap,
+}
+impl Azimuth {
+	pub fn create(
+		client: &Client,
+		resume: &Resume,
+		pending_movement: PendingMovement,
+		mouse_event_rx: EventReceiver<MouseEvent>,
+		keyboard_event_rx: EventReceiver<KeyboardEvent>,
+	) -> Result<Self> {
+		let settings = resume.settings.clone();
+
+		let pointer = PointerInputMethod::create(client.get_root(), Transform::identity(), None)?;
+		let lines = Lines::create(
+			&pointer,
+			Transform::from_position([0.0, 0.0, -settings.cursor.distance]),
+			&CursorState::Idle.points(&settings.cursor, 1.0),
+			true,
+		)?;
+		let ray = Lines::create(
+			&pointer,
+			Transform::identity(),
+			&ray_points(&settings.ray, settings.ray.length),
+			false,
+		)?;
+		let ray_visible = settings.ray.visible;
+		ray.set_enabled(ray_visible)?;
+		let keyboard_pulse_sender =
+			PulseSender::create(&pointer, Transform::identity(), &KEYBOARD_MASK)?
+				.wrap(KeyboardReceivers::default())?;
+		let mouse_pulse_sender = PulseSender::create(&pointer, Transform::identity(), &MOUSE_MASK)?
+			.wrap(MouseReceivers::default())?;
+		let hit_tester = HitTester::spawn(
+			pointer.alias(),
+			keyboard_pulse_sender.wrapped().clone(),
+			mouse_pulse_sender.wrapped().clone(),
+		);
+
+		let azimuth = Azimuth {
+			settings,
+			settings_rx: resume.settings_rx.clone(),
+			known_devices: HashSet::new(),
+			pointer,
+			pending_movement,
+			mouse_event_rx,
+			deferred_mouse_events: Vec::new(),
+			keyboard_event_rx,
+			modifiers: ModifierTracker::default(),
+			keyboard_pulse_sender,
+			mouse_pulse_sender,
+			lines,
+			cursor_state: CursorState::Idle,
+			ray,
+			ray_visible,
+			swallowed_keys: HashSet::new(),
+			hit_tester,
+			hit: PointerHit::default(),
+			hover: HoverTracker::default(),
+			capture: None,
+			yaw: resume.yaw,
+			pitch: resume.pitch,
+			datamap: Datamap::default(),
+		};
+		azimuth.update_rotation();
+		Ok(azimuth)
+	}
+
+	pub fn pointer(&self) -> &PointerInputMethod {
+		&self.pointer
+	}
+
+	/// Never send input to these receivers, for azimuth's own
+	pub(crate) fn ignore_receivers(&self, keyboard_receiver_path: &str, mouse_receiver_path: &str) {
+		self.keyboard_pulse_sender
+			.lock_wrapped()
+			.receivers
+			.ignore(keyboard_receiver_path);
+		self.mouse_pulse_sender
+			.lock_wrapped()
+			.receivers
+			.ignore(mouse_receiver_path);
+	}
+
+	/// Save what the next connection should pick up from
+	pub fn save(&self, resume: &mut Resume) {
+		resume.settings = self.settings.clone();
+		resume.yaw = self.yaw;
+		resume.pitch = self.pitch;
+	}
+
+	fn apply_settings(&mut self, settings: Settings) {
+		if settings.ray.visible != self.settings.ray.visible {
+			self.ray_visible = settings.ray.visible;
+		}
+		self.settings = settings;
+		self.update_cursor();
+		self.update_ray();
+		self.pitch = clamp_pitch(self.pitch, &self.settings);
+		self.update_rotation();
+	}
+	fn update_rotation(&self) {
+		let rotation_x = Quat::from_rotation_x(-self.pitch.to_radians());
+		let rotation_y = Quat::from_rotation_y(-self.yaw.to_radians());
+		warn_err!(
+			self.pointer.set_rotation(None, rotation_y * rotation_x),
+			"rotate the pointer"
+		);
+	}
+
+	/// Put the cursor where the ray hits, keeping the same size in view no matter the distance
+	fn update_cursor(&self) {
+		let cursor = &self.settings.cursor;
+		let (distance, scale) = cursor_placement(self.hit.distance, cursor);
+		warn_err!(
+			self.lines.set_transform(
+				None,
+				Transform::from_position_scale([0.0, 0.0, -distance], [scale; 3]),
+			),
+			"move the cursor"
+		);
+		// line thickness ignores the node's scale
+		warn_err!(
+			self.lines
+				.update_points(&self.cursor_state.points(cursor, scale)),
+			"update the cursor lines"
+		);
+	}
+
+	fn update_ray(&self) {
+		warn_err!(
+			self.ray.set_enabled(self.ray_visible),
+			"show or hide the ray"
+		);
+		if self.ray_visible {
+			let length = drawable_distance(self.hit.distance).unwrap_or(self.settings.ray.length);
+			warn_err!(
+				self.ray
+					.update_points(&ray_points(&self.settings.ray, length)),
+				"update the ray lines"
+			);
+		}
+	}
+
+	/// Act on any hotkeys in `key_event`, taking them out of it
+	fn handle_hotkeys(&mut self, key_event: &mut KeyboardEvent) {
+		if let Some(keys_up) = &mut key_event.keys_up {
+			keys_up.retain(|key| !self.swallowed_keys.remove(key));
+		}
+		let Some(keys_down) = &mut key_event.keys_down else {return};
+		let modifiers = self.modifiers.state();
+		let mut toggle_ray = false;
+		keys_down.retain(|key| {
+			let Some(keysym) = self.modifiers.keysym(*key) else {return true};
+			let is_toggle_ray = self
+				.settings
+				.ray
+				.toggle_key
+				.as_ref()
+				.is_some_and(|hotkey| hotkey.matches(keysym, modifiers));
+			if !is_toggle_ray {
+				return true;
+			}
+			toggle_ray = !toggle_ray;
+			self.swallowed_keys.insert(*key);
+			false
+		});
+		if toggle_ray {
+			self.ray_visible = !self.ray_visible;
+			self.update_ray();
+		}
+	}
+
+	fn send_keys(&self, key_events: Vec<KeyboardEvent>) {
+		let mut keyboard_receivers = self.keyboard_pulse_sender.lock_wrapped();
+		for key_event in key_events {
+			keyboard_receivers.send(self.keyboard_pulse_sender.node(), key_event);
+		}
+	}
+
+	fn forward_mouse(&self, mouse_events: Vec<MouseReceiverEvent>) {
+		let mut mouse_receivers = self.mouse_pulse_sender.lock_wrapped();
+		for mouse_event in mouse_events {
+			mouse_receivers.send(
+				self.mouse_pulse_sender.node(),
+				mouse_event,
+				self.hit.mouse_target.as_deref(),
+			);
+		}
+	}
+}
+impl RootHandler for Azimuth {
+	fn frame(&mut self, info: FrameInfo) {
+		let Ok(client) = self.pointer.client() else {return};
+		warn_err!(
+			self.pointer.set_position(Some(client.get_hmd()), [0.0; 3]),
+			"move the pointer to the head"
+		);
+
+		if let Some(settings_rx) = &mut self.settings_rx {
+			if settings_rx.has_changed().unwrap_or(false) {
+				let settings = settings_rx.borrow_and_update().clone();
+				self.apply_settings(settings);
+			}
+		}
+
+		let mouse_events = std::mem::take(&mut self.deferred_mouse_events)
+			.into_iter()
+			.chain(std::iter::from_fn(|| self.mouse_event_rx.try_recv()));
+		let mut mouse_input = MouseInput::collect(&mut self.datamap, mouse_events);
+		self.deferred_mouse_events = std::mem::take(&mut mouse_input.deferred);
+		let overflows = self.mouse_event_rx.take_overflows();
+		if overflows > 0 {
+			warn_limited!("{overflows} mouse events came in faster than frames could take them");
+		}
+		let movement = self.pending_movement.take();
+		if !movement.is_empty() {
+			// movement is kept apart per mouse, so each is accelerated by its own speed
+			for (device, delta) in movement {
+				if self.known_devices.insert(device.clone()) {
+					tracing::debug!("New mouse {device}");
+				}
+				let gain = self
+					.settings
+					.acceleration_for(&device)
+					.gain(delta.length(), info.delta as f32);
+				(self.yaw, self.pitch) = turn(self.yaw, self.pitch, delta, gain, &self.settings);
+			}
+			self.update_rotation();
+		}
+
+		let mut key_events = Vec::new();
+		while let Some(mut key_event) = self.keyboard_event_rx.try_recv() {
+			self.modifiers.update(&key_event);
+			self.handle_hotkeys(&mut key_event);
+			key_events.push(key_event);
+		}
+		let overflows = self.keyboard_event_rx.take_overflows();
+		if overflows > 0 {
+			warn_limited!("{overflows} key events came in faster than frames could take them");
+		}
+		self.datamap.modifiers = self.modifiers.state();
+
+		let hit = self.hit_tester.latest();
+		let hover_changes = self.hover.update(
+			&hit.handlers,
+			self.capture.as_deref(),
+			info.elapsed,
+			self.settings.hover_leave_delay,
+		);
+		self.datamap.hover_entered = hover_changes.entered;
+		self.datamap.hover_left = hover_changes.left;
+		self.datamap.hit_points = hit
+			.hit_points
+			.iter()
+			.map(|(uid, point)| (uid.clone(), (*point).into()))
+			.collect();
+
+		warn_err!(
+			self.pointer
+				.set_datamap(self.datamap.serialize_pulse_data().as_slice()),
+			"set the pointer datamap"
+		);
+
+		let cursor_state = CursorState::new(
+			self.hover.is_hovering(),
+			hit.keyboard_target.is_some(),
+			self.datamap.select > 0.0,
+			self.datamap.grab > 0.0,
+		);
+		let distance_changed = hit.distance != self.hit.distance;
+		let cursor_changed = distance_changed || cursor_state != self.cursor_state;
+		self.hit = hit;
+		self.cursor_state = cursor_state;
+		if cursor_changed {
+			self.update_cursor();
+		}
+		if distance_changed && self.ray_visible {
+			self.update_ray();
+		}
+		if !self.datamap.any_button_held() {
+			self.capture = None;
+		} else if self.capture.is_none() {
+			self.capture = Some(self.hit.handlers.clone());
+		}
+		// anything added after the captured handlers would break the frozen order
+		let linger = match self.capture {
+			Some(_) => Vec::new(),
+			None => self.hover.lingering(&self.hit.handlers),
+		};
+		self.hit_tester.request(
+			self.settings.tie_epsilon,
+			self.capture.clone(),
+			linger,
+			self.settings.enclosing_receivers,
+		);
+
+		self.keyboard_pulse_sender.lock_wrapped().update_focus(
+			self.keyboard_pulse_sender.node(),
+			self.settings.keyboard_focus,
+			self.hit.keyboard_target.as_deref(),
+			mouse_input.clicked,
+			&self.modifiers.held_keys(),
+		);
+		if !key_events.is_empty() {
+			self.send_keys(key_events);
+		}
+		if !mouse_input.forwarded.is_empty() {
+			self.forward_mouse(mouse_input.forwarded);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn forwarded(mouse: &str) -> MouseReceiverEvent {
+		MouseReceiverEvent {
+			mouse: mouse.to_string(),
+			delta: None,
+			scroll_distance: None,
+			scroll_steps: None,
+			buttons_up: None,
+			buttons_down: Some(vec![BTN_LEFT!()]),
+		}
+	}
+
+	#[test]
+	fn turning_scales_by_sensitivity_and_gain() {
+		let settings = Settings {
+			sensitivity: 0.5,
+			..Default::default()
+		};
+		assert_eq!(
+			turn(10.0, 0.0, Vec2::new(4.0, -2.0), 2.0, &settings),
+			(14.0, -2.0)
+		);
+	}
+
+	#[test]
+	fn turning_can_invert_y() {
+		let settings = Settings {
+			sensitivity: 1.0,
+			invert_y: true,
+			..Default::default()
+		};
+		assert_eq!(
+			turn(0.0, 0.0, Vec2::new(1.0, 3.0), 1.0, &settings),
+			(1.0, -3.0)
+		);
+	}
+
+	#[test]
+	fn pitch_is_clamped_but_yaw_is_not() {
+		let settings = Settings {
+			sensitivity: 1.0,
+			min_pitch: -45.0,
+			max_pitch: 60.0,
+			..Default::default()
+		};
+		assert_eq!(
+			turn(350.0, 50.0, Vec2::new(30.0, 20.0), 1.0, &settings),
+			(380.0, 60.0)
+		);
+		assert_eq!(
+			turn(0.0, -40.0, Vec2::new(0.0, -20.0), 1.0, &settings),
+			(0.0, -45.0)
+		);
+	}
+
+	#[test]
+	fn overlapping_pitch_limits_dont_panic() {
+		let settings = Settings {
+			min_pitch: 30.0,
+			max_pitch: -30.0,
+			..Default::default()
+		};
+		assert_eq!(clamp_pitch(0.0, &settings), -30.0);
+	}
+
+	#[test]
+	fn scrolling_adds_up_over_a_frame() {
+		let mut datamap = Datamap::default();
+		let input = MouseInput::collect(
+			&mut datamap,
+			[
+				MouseEvent::Scroll { x: 0.5, y: 1.0 },
+				MouseEvent::ScrollDiscrete { x: 0.0, y: -1.0 },
+				MouseEvent::Scroll { x: 0.25, y: 2.0 },
+				MouseEvent::ScrollDiscrete { x: 1.0, y: -1.0 },
+			],
+		);
+		assert_eq!(input.scroll_continuous, Vec2::new(0.75, 3.0));
+		assert_eq!(input.scroll_discrete, Vec2::new(1.0, -2.0));
+		assert_eq!(datamap.scroll_continuous, [0.75, 3.0].into());
+		assert_eq!(datamap.scroll_discrete, [1.0, -2.0].into());
+		assert_eq!(datamap.scroll, [1.75, 1.0].into());
+	}
+
+	#[test]
+	fn scrolling_resets_each_frame() {
+		let mut datamap = Datamap::default();
+		MouseInput::collect(&mut datamap, [MouseEvent::Scroll { x: 1.0, y: 1.0 }]);
+		MouseInput::collect(&mut datamap, []);
+		assert_eq!(datamap.scroll_continuous, [0.0, 0.0].into());
+		assert_eq!(datamap.scroll, [0.0, 0.0].into());
+	}
+
+	#[test]
+	fn datamap_keeps_the_keys_molecules_reads() {
+		let data = Datamap::default().serialize_pulse_data();
+		let datamap = flexbuffers::Reader::get_root(data.as_slice())
+			.unwrap()
+			.as_map();
+		let keys: Vec<_> = datamap.iter_keys().collect();
+		for key in ["select", "grab", "scroll"] {
+			assert!(keys.contains(&key), "{key} is missing from {keys:?}");
+		}
+		assert_eq!(datamap.idx("scroll").as_vector().len(), 2);
+	}
+
+	#[test]
+	fn click_within_a_frame_is_released_the_next() {
+		let mut datamap = Datamap::default();
+		let input = MouseInput::collect(
+			&mut datamap,
+			[MouseEvent::LeftClick(true), MouseEvent::LeftClick(false)],
+		);
+		assert!(input.clicked);
+		assert_eq!(datamap.select, 1.0);
+		let input = MouseInput::collect(&mut datamap, input.deferred);
+		assert!(!input.clicked);
+		assert!(input.deferred.is_empty());
+		assert_eq!(datamap.select, 0.0);
+	}
+
+	#[test]
+	fn double_click_within_a_frame_plays_out_over_frames() {
+		let mut datamap = Datamap::default();
+		let clicks = [true, false, true, false].map(MouseEvent::LeftClick);
+		let mut input = MouseInput::collect(&mut datamap, clicks);
+		let mut select = vec![datamap.select];
+		while !input.deferred.is_empty() {
+			input = MouseInput::collect(&mut datamap, input.deferred);
+			select.push(datamap.select);
+		}
+		assert_eq!(select, [1.0, 0.0, 1.0, 0.0]);
+	}
+
+	#[test]
+	fn deferring_one_button_leaves_the_others() {
+		let mut datamap = Datamap::default();
+		let input = MouseInput::collect(
+			&mut datamap,
+			[
+				MouseEvent::LeftClick(true),
+				MouseEvent::LeftClick(false),
+				MouseEvent::RightClick(true),
+			],
+		);
+		assert_eq!((datamap.select, datamap.grab), (1.0, 1.0));
+		assert!(matches!(
+			input.deferred.as_slice(),
+			[MouseEvent::LeftClick(false)]
+		));
+	}
+
+	#[test]
+	fn release_is_not_a_click() {
+		let mut datamap = Datamap {
+			select: 1.0,
+			..Default::default()
+		};
+		let input = MouseInput::collect(&mut datamap, [MouseEvent::LeftClick(false)]);
+		assert!(!input.clicked);
+		assert_eq!(datamap.select, 0.0);
+	}
+
+	#[test]
+	fn buttons_stay_held_across_frames() {
+		let mut datamap = Datamap::default();
+		MouseInput::collect(&mut datamap, [MouseEvent::RightClick(true)]);
+		let input = MouseInput::collect(&mut datamap, []);
+		assert!(!input.clicked);
+		assert_eq!(datamap.grab, 1.0);
+		assert!(datamap.any_button_held());
+		MouseInput::collect(&mut datamap, [MouseEvent::RightClick(false)]);
+		assert!(!datamap.any_button_held());
+	}
+
+	#[test]
+	fn buttons_map_to_the_datamap() {
+		assert!(matches!(
+			MouseEvent::button(BTN_MIDDLE!(), true),
+			Some(MouseEvent::MiddleClick(true))
+		));
+		assert!(matches!(
+			MouseEvent::button(BTN_SIDE!(), false),
+			Some(MouseEvent::BackClick(false))
+		));
+		assert!(matches!(
+			MouseEvent::button(BTN_EXTRA!(), true),
+			Some(MouseEvent::ForwardClick(true))
+		));
+		assert!(MouseEvent::button(0, true).is_none());
+	}
+
+	#[test]
+	fn forwarded_events_are_kept_in_order() {
+		let mut datamap = Datamap::default();
+		let input = MouseInput::collect(
+			&mut datamap,
+			[
+				MouseEvent::Forward(forwarded("a")),
+				MouseEvent::LeftClick(true),
+				MouseEvent::Forward(forwarded("b")),
+			],
+		);
+		let mice: Vec<_> = input
+			.forwarded
+			.iter()
+			.map(|event| event.mouse.as_str())
+			.collect();
+		assert_eq!(mice, ["a", "b"]);
+		assert_eq!(datamap.select, 1.0);
+	}
+}
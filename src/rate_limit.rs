@@ -0,0 +1,59 @@
+use std::{
+	sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How often each place can log a warning, in milliseconds
+const INTERVAL_MS: u64 = 5000;
+
+/// Limits how often something is logged, so an error that happens every frame doesn't flood the log
+pub struct RateLimit {
+	/// when this last allowed a log, in milliseconds since the unix epoch
+	last: AtomicU64,
+	/// how many logs were held back since then
+	suppressed: AtomicUsize,
+}
+impl RateLimit {
+	pub const fn new() -> Self {
+		RateLimit {
+			last: AtomicU64::new(0),
+			suppressed: AtomicUsize::new(0),
+		}
+	}
+
+	/// If it's time to log again, how many logs were held back since the last one
+	pub fn check(&self) -> Option<usize> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_millis() as u64;
+		let last = self.last.load(Ordering::Relaxed);
+		if last != 0 && now.saturating_sub(last) < INTERVAL_MS {
+			self.suppressed.fetch_add(1, Ordering::Relaxed);
+			return None;
+		}
+		self.last.store(now, Ordering::Relaxed);
+		Some(self.suppressed.swap(0, Ordering::Relaxed))
+	}
+}
+
+/// `tracing::warn!`, but at most once every few seconds from each place it's used
+macro_rules! warn_limited {
+	($($arg:tt)+) => {{
+		static LIMIT: $crate::rate_limit::RateLimit = $crate::rate_limit::RateLimit::new();
+		if let Some(suppressed) = LIMIT.check() {
+			tracing::warn!(suppressed, $($arg)+);
+		}
+	}};
+}
+
+/// Warn about `result`'s error with [`warn_limited!`], for calls there's nothing else to do about when they fail.
+///
+/// `warn_err!(pointer.set_position(...), "move the pointer")` logs "Couldn't move the pointer: {error}".
+macro_rules! warn_err {
+	($result:expr, $what:literal) => {
+		if let Err(e) = $result {
+			warn_limited!(concat!("Couldn't ", $what, ": {}"), e);
+		}
+	};
+}
@@ -0,0 +1,420 @@
+use crate::focus::{FocusMode, KeyboardFocus};
+use serde::{Deserialize, Serialize};
+use stardust_xr_fusion::{
+	data::{NewReceiverInfo, PulseReceiver, PulseSender, PulseSenderHandler},
+	fields::UnknownField,
+	node::NodeType,
+};
+use stardust_xr_molecules::{keyboard::KeyboardEvent, mouse::MouseEvent};
+use std::collections::{HashMap, HashSet};
+
+/// What to do with keyboard and mouse receivers whose field the pointer is inside
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnclosingPolicy {
+	/// Only target receivers the ray enters from outside
+	#[default]
+	Ignore,
+	/// Target them before anything the ray hits, the one the pointer is deepest inside first
+	Target,
+}
+
+/// A pulse receiver found by one of azimuth's pulse senders
+pub struct Receiver {
+	pub receiver: PulseReceiver,
+	pub field: UnknownField,
+}
+
+/// Keys or buttons a receiver was sent a press for and no release yet
+#[derive(Debug, Default)]
+pub struct Held(HashSet<u32>);
+impl Held {
+	/// Leave out releases of anything that isn't held and presses of anything that already is,
+	/// returning whether any are left.
+	pub fn balance(&mut self, up: &mut Option<Vec<u32>>, down: &mut Option<Vec<u32>>) -> bool {
+		if let Some(up) = up {
+			up.retain(|key| self.0.remove(key));
+		}
+		if let Some(down) = down {
+			down.retain(|key| self.0.insert(*key));
+		}
+		let is_empty = |keys: &Option<Vec<u32>>| keys.as_deref().unwrap_or_default().is_empty();
+		!is_empty(up) || !is_empty(down)
+	}
+
+	/// Release everything, returning what was held
+	pub fn release_all(&mut self) -> Vec<u32> {
+		let mut released: Vec<_> = self.0.drain().collect();
+		released.sort();
+		released
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+/// The receivers a pulse sender found, by uid
+#[derive(Default)]
+pub struct Receivers {
+	receivers: HashMap<String, Receiver>,
+	/// uids of azimuth's own receivers, which are never targeted
+	own: HashSet<String>,
+}
+impl Receivers {
+	/// Never target the receiver at `path`, for azimuth's own receivers
+	pub fn ignore(&mut self, path: &str) {
+		let uid = path.rsplit('/').next().unwrap_or(path);
+		self.own.insert(uid.to_string());
+	}
+
+	/// The uid and field of every receiver that can be targeted
+	pub fn targets(&self) -> Vec<(String, UnknownField)> {
+		self.receivers
+			.iter()
+			.filter(|(uid, _)| !self.own.contains(*uid))
+			.map(|(uid, receiver)| (uid.clone(), receiver.field.alias()))
+			.collect()
+	}
+
+	fn insert(&mut self, uid: String, receiver: PulseReceiver, field: UnknownField) {
+		self.receivers.insert(uid, Receiver { receiver, field });
+	}
+
+	/// Each receiver's uid and node path, for debugging
+	pub fn describe(&self, focused: Option<&str>) -> Vec<String> {
+		let mut receivers = self
+			.receivers
+			.iter()
+			.map(|(uid, receiver)| {
+				let path = receiver.receiver.node().get_path().unwrap_or_default();
+				let focused = if focused == Some(uid.as_str()) {
+					" (focused)"
+				} else {
+					""
+				};
+				format!("{uid} at {path}{focused}")
+			})
+			.collect::<Vec<_>>();
+		receivers.sort();
+		receivers
+	}
+}
+
+/// Which keyboard receiver gets the keys and which keys each one has held, by uid
+#[derive(Debug, Default)]
+pub struct KeyRouting {
+	focus: KeyboardFocus,
+	held: HashMap<String, Held>,
+	/// the last keymap sent by the keyboard, so receivers that gain focus can be given it
+	keymap: Option<String>,
+}
+impl KeyRouting {
+	pub fn focused(&self) -> Option<&str> {
+		self.focus.focused()
+	}
+
+	/// The focused receiver's uid and `event` to send it.
+	///
+	/// Releases of keys it was never sent a press for and presses of keys it already has held are left out,
+	/// so every receiver sees balanced presses and releases.
+	pub fn route(&mut self, mut event: KeyboardEvent) -> Option<(String, KeyboardEvent)> {
+		if let Some(keymap) = &event.keymap {
+			self.keymap = Some(keymap.clone());
+		}
+		let uid = self.focus.focused()?;
+		let held = self.held.entry(uid.to_string()).or_default();
+		if !held.balance(&mut event.keys_up, &mut event.keys_down) && event.keymap.is_none() {
+			return None;
+		}
+		Some((uid.to_string(), event))
+	}
+
+	/// Move focus like [`KeyboardFocus::update`], returning the events that go with it by receiver uid.
+	///
+	/// The receiver losing focus is sent a release for every key it still has held so none get stuck,
+	/// and the one gaining focus is sent the keymap and a press for each of `held_modifiers`.
+	pub fn update_focus(
+		&mut self,
+		mode: FocusMode,
+		target: Option<&str>,
+		clicked: bool,
+		held_modifiers: &[u32],
+	) -> Option<Vec<(String, KeyboardEvent)>> {
+		let old_focus = self.focus.focused().map(str::to_string);
+		if !self.focus.update(mode, target, clicked) {
+			return None;
+		}
+		let mut events = Vec::new();
+		if let Some(old) = old_focus {
+			let keys_up = self.held.remove(&old).unwrap_or_default().release_all();
+			if !keys_up.is_empty() {
+				events.push((old, KeyboardEvent::new(None, Some(keys_up), None)));
+			}
+		}
+		let Some(new) = self.focus.focused() else {return Some(events)};
+		if self.keymap.is_none() && held_modifiers.is_empty() {
+			return Some(events);
+		}
+		let mut keys_down = Some(held_modifiers.to_vec());
+		let held = self.held.entry(new.to_string()).or_default();
+		held.balance(&mut None, &mut keys_down);
+		let mut event = KeyboardEvent::new(None, None, keys_down);
+		event.keymap = self.keymap.clone();
+		events.push((new.to_string(), event));
+		Some(events)
+	}
+
+	/// Forget everything about the receiver `uid`, taking focus from it if it has it
+	pub fn receiver_dropped(&mut self, uid: &str) {
+		self.focus.receiver_dropped(uid);
+		self.held.remove(uid);
+	}
+}
+
+/// Every keyboard receiver azimuth could send keys to, along with which one has focus
+#[derive(Default)]
+pub struct KeyboardReceivers {
+	pub receivers: Receivers,
+	routing: KeyRouting,
+}
+impl KeyboardReceivers {
+	/// Send `event` to the focused receiver, see [`KeyRouting::route`]
+	#[tracing::instrument(level = "debug", name = "send_keys", skip_all)]
+	pub fn send(&mut self, sender: &PulseSender, event: KeyboardEvent) {
+		let Some((uid, event)) = self.routing.route(event) else {return};
+		let Some(focused) = self.receivers.receivers.get(&uid) else {return};
+		tracing::debug!(receiver = uid, "Sending a key event");
+		warn_err!(
+			sender.send_data(&focused.receiver, &event.serialize_pulse_data()),
+			"send a key event"
+		);
+	}
+
+	/// Move focus and send the releases and presses that go with it, see [`KeyRouting::update_focus`].
+	/// Returns whether the focus changed.
+	#[tracing::instrument(level = "debug", skip(self, sender))]
+	pub fn update_focus(
+		&mut self,
+		sender: &PulseSender,
+		mode: FocusMode,
+		target: Option<&str>,
+		clicked: bool,
+		held_modifiers: &[u32],
+	) -> bool {
+		let old_focus = self.routing.focused().map(str::to_string);
+		let Some(events) = self
+			.routing
+			.update_focus(mode, target, clicked, held_modifiers) else {return false};
+		tracing::debug!(from = ?old_focus, to = ?self.routing.focused(), "Keyboard focus moved");
+		for (uid, event) in events {
+			let Some(receiver) = self.receivers.receivers.get(&uid) else {continue};
+			warn_err!(
+				sender.send_data(&receiver.receiver, &event.serialize_pulse_data()),
+				"move held keys along with keyboard focus"
+			);
+		}
+		true
+	}
+}
+impl PulseSenderHandler for KeyboardReceivers {
+	fn new_receiver(
+		&mut self,
+		info: NewReceiverInfo,
+		receiver: PulseReceiver,
+		field: UnknownField,
+	) {
+		self.receivers.insert(info.uid.clone(), receiver, field);
+		tracing::debug!(
+			"New keyboard receiver {}, receivers are now {:#?}",
+			info.uid,
+			self.receivers.describe(self.routing.focused())
+		);
+	}
+
+	fn drop_receiver(&mut self, uid: &str) {
+		self.receivers.receivers.remove(uid);
+		self.routing.receiver_dropped(uid);
+		tracing::debug!(
+			"Keyboard receiver {uid} dropped, receivers are now {:#?}",
+			self.receivers.describe(self.routing.focused())
+		);
+	}
+}
+
+/// Every mouse receiver azimuth could pass mouse buttons and scrolling on to
+#[derive(Default)]
+pub struct MouseReceivers {
+	pub receivers: Receivers,
+	/// buttons held on each receiver, by uid
+	held: HashMap<String, Held>,
+}
+impl MouseReceivers {
+	/// The receiver that still has buttons held, if any
+	fn holding(&self) -> Option<&str> {
+		self.held
+			.iter()
+			.find(|(_, held)| !held.is_empty())
+			.map(|(uid, _)| uid.as_str())
+	}
+
+	/// Send `event` to `target`, the receiver under the pointer.
+	///
+	/// Once a receiver is sent a button press it gets everything until all its buttons are released,
+	/// so a drag that leaves it doesn't leave buttons stuck down.
+	#[tracing::instrument(level = "debug", name = "forward_mouse", skip(self, sender, event))]
+	pub fn send(&mut self, sender: &PulseSender, mut event: MouseEvent, target: Option<&str>) {
+		let Some(uid) = self.holding().or(target).map(str::to_string) else {return};
+		let Some(receiver) = self.receivers.receivers.get(&uid) else {return};
+		let held = self.held.entry(uid.clone()).or_default();
+		if !held.balance(&mut event.buttons_up, &mut event.buttons_down)
+			&& event.scroll_distance.is_none()
+			&& event.scroll_steps.is_none()
+		{
+			return;
+		}
+		tracing::debug!(receiver = uid, "Forwarding a mouse event");
+		warn_err!(
+			sender.send_data(&receiver.receiver, &event.serialize_pulse_data()),
+			"forward a mouse event"
+		);
+	}
+}
+impl PulseSenderHandler for MouseReceivers {
+	fn new_receiver(
+		&mut self,
+		info: NewReceiverInfo,
+		receiver: PulseReceiver,
+		field: UnknownField,
+	) {
+		self.receivers.insert(info.uid.clone(), receiver, field);
+		tracing::debug!(
+			"New mouse receiver {}, receivers are now {:#?}",
+			info.uid,
+			self.receivers.describe(None)
+		);
+	}
+
+	fn drop_receiver(&mut self, uid: &str) {
+		self.receivers.receivers.remove(uid);
+		self.held.remove(uid);
+		tracing::debug!(
+			"Mouse receiver {uid} dropped, receivers are now {:#?}",
+			self.receivers.describe(None)
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use input_event_codes::{KEY_A, KEY_B, KEY_LEFTSHIFT};
+
+	fn keys(up: &[u32], down: &[u32]) -> KeyboardEvent {
+		let keys = |keys: &[u32]| (!keys.is_empty()).then(|| keys.to_vec());
+		KeyboardEvent::new(None, keys(up), keys(down))
+	}
+
+	fn focused_on(uid: &str) -> KeyRouting {
+		let mut routing = KeyRouting::default();
+		routing.update_focus(FocusMode::Click, Some(uid), true, &[]);
+		routing
+	}
+
+	#[test]
+	fn unmatched_releases_are_left_out() {
+		let mut held = Held::default();
+		let (mut up, mut down) = (Some(vec![KEY_A!()]), None);
+		assert!(!held.balance(&mut up, &mut down));
+		assert_eq!(up, Some(vec![]));
+
+		let (mut up, mut down) = (None, Some(vec![KEY_A!()]));
+		assert!(held.balance(&mut up, &mut down));
+		let (mut up, mut down) = (Some(vec![KEY_A!(), KEY_B!()]), None);
+		assert!(held.balance(&mut up, &mut down));
+		assert_eq!(up, Some(vec![KEY_A!()]));
+		assert!(held.is_empty());
+	}
+
+	#[test]
+	fn repeated_presses_are_left_out() {
+		let mut held = Held::default();
+		held.balance(&mut None, &mut Some(vec![KEY_A!()]));
+		let mut down = Some(vec![KEY_A!(), KEY_B!()]);
+		assert!(held.balance(&mut None, &mut down));
+		assert_eq!(down, Some(vec![KEY_B!()]));
+		assert_eq!(held.release_all(), [KEY_A!(), KEY_B!()]);
+	}
+
+	#[test]
+	fn keys_go_to_the_focused_receiver() {
+		let mut routing = KeyRouting::default();
+		assert!(routing.route(keys(&[], &[KEY_A!()])).is_none());
+		let mut routing = focused_on("a");
+		let (uid, event) = routing.route(keys(&[], &[KEY_A!()])).unwrap();
+		assert_eq!(uid, "a");
+		assert_eq!(event.keys_down, Some(vec![KEY_A!()]));
+	}
+
+	#[test]
+	fn releases_pressed_before_focus_are_filtered() {
+		let mut routing = focused_on("a");
+		assert!(routing.route(keys(&[KEY_A!()], &[])).is_none());
+	}
+
+	#[test]
+	fn moving_focus_releases_held_keys_and_presses_modifiers() {
+		let mut routing = focused_on("a");
+		routing.route(keys(&[], &[KEY_LEFTSHIFT!(), KEY_A!()]));
+		let events = routing
+			.update_focus(FocusMode::Click, Some("b"), true, &[KEY_LEFTSHIFT!()])
+			.unwrap();
+		let [(old, release), (new, press)] = events.as_slice() else {
+			panic!("expected a release and a press, got {events:?}")
+		};
+		assert_eq!((old.as_str(), new.as_str()), ("a", "b"));
+		assert_eq!(release.keys_up, Some(vec![KEY_A!(), KEY_LEFTSHIFT!()]));
+		assert_eq!(press.keys_down, Some(vec![KEY_LEFTSHIFT!()]));
+
+		// "b" got the shift press, so its release goes through, but "a" never reached it
+		let (uid, event) = routing
+			.route(keys(&[KEY_LEFTSHIFT!(), KEY_A!()], &[]))
+			.unwrap();
+		assert_eq!(uid, "b");
+		assert_eq!(event.keys_up, Some(vec![KEY_LEFTSHIFT!()]));
+	}
+
+	#[test]
+	fn gaining_focus_gets_the_keymap() {
+		let mut routing = focused_on("a");
+		let mut event = keys(&[], &[]);
+		event.keymap = Some("keymap".to_string());
+		assert!(routing.route(event).is_some());
+		let events = routing
+			.update_focus(FocusMode::Click, Some("b"), true, &[])
+			.unwrap();
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].1.keymap.as_deref(), Some("keymap"));
+	}
+
+	#[test]
+	fn no_focus_change_sends_nothing() {
+		let mut routing = focused_on("a");
+		assert!(routing
+			.update_focus(FocusMode::Click, Some("b"), false, &[])
+			.is_none());
+	}
+
+	#[test]
+	fn dropped_receivers_lose_focus_and_held_keys() {
+		let mut routing = focused_on("a");
+		routing.route(keys(&[], &[KEY_A!()]));
+		routing.receiver_dropped("a");
+		assert_eq!(routing.focused(), None);
+		assert!(routing.route(keys(&[KEY_A!()], &[])).is_none());
+		let events = routing
+			.update_focus(FocusMode::Click, Some("a"), true, &[])
+			.unwrap();
+		assert!(events.is_empty());
+	}
+}
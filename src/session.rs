@@ -0,0 +1,191 @@
+use crate::{
+	events::{event_channel, PendingMovement},
+	pointer::{Azimuth, MouseEvent},
+	settings::Settings,
+};
+use color_eyre::Result;
+use glam::Vec2;
+use stardust_xr_fusion::{
+	client::Client,
+	core::{messenger::MessengerError, schemas::flex::flexbuffers, values::Transform},
+	fields::SphereField,
+	node::NodeType,
+	Mutex,
+};
+use stardust_xr_molecules::{
+	data::InlinePulseReceiver,
+	keyboard::{KeyboardEvent, KEYBOARD_MASK},
+	mouse::{MouseEvent as MouseReceiverEvent, MOUSE_MASK},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// Wait this long before trying to connect again, doubling with every failed try up to `MAX_RECONNECT_DELAY`
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Run azimuth, connecting again whenever the server can't be reached or the connection to it breaks
+pub async fn stay_connected(mut resume: Resume) -> Result<()> {
+	let mut reconnect_delay = MIN_RECONNECT_DELAY;
+	loop {
+		match connect(&mut resume).await {
+			Ok(Disconnect::Stopped) => return Ok(()),
+			Ok(Disconnect::Lost(e)) => {
+				tracing::warn!("Lost connection to the Stardust server, reconnecting: {e}");
+				reconnect_delay = MIN_RECONNECT_DELAY;
+				tokio::time::sleep(reconnect_delay).await;
+			}
+			Err(e) => {
+				tracing::warn!(
+					"Couldn't connect to the Stardust server, retrying in {reconnect_delay:?}: {e}"
+				);
+				tokio::time::sleep(reconnect_delay).await;
+				reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+			}
+		}
+	}
+}
+
+/// Why a connection to the server ended
+enum Disconnect {
+	/// the client was told to stop
+	Stopped,
+	/// the connection broke
+	Lost(MessengerError),
+}
+
+/// What carries over from one connection to the server to the next
+pub struct Resume {
+	pub settings: Settings,
+	/// new settings from [`Settings::watch`]
+	pub settings_rx: Option<watch::Receiver<Settings>>,
+	pub yaw: f32,
+	pub pitch: f32,
+}
+
+/// Azimuth's pointer on a client, along with the pulse receivers that give it mouse and keyboard input.
+///
+/// The pointer only updates when its `frame` is called, so either make it the client's root handler
+/// or call it from another root handler.
+pub struct Session {
+	pub azimuth: Arc<Mutex<Azimuth>>,
+	_field: SphereField,
+	_mouse_pulse_receiver: InlinePulseReceiver,
+	_keyboard_pulse_receiver: InlinePulseReceiver,
+}
+impl Session {
+	pub fn create(client: &Client, resume: &Resume) -> Result<Self> {
+		let pending_movement = PendingMovement::default();
+		let (mouse_event_tx, mouse_event_rx) = event_channel(64);
+		let (keyboard_event_tx, keyboard_event_rx) = event_channel(64);
+		let azimuth = Azimuth::create(
+			client,
+			resume,
+			pending_movement.clone(),
+			mouse_event_rx,
+			keyboard_event_rx,
+		)?;
+		let field = SphereField::create(azimuth.pointer(), [0.0; 3], 0.0)?;
+		let mouse_pulse_receiver = InlinePulseReceiver::create(
+			azimuth.pointer(),
+			Transform::default(),
+			&field,
+			&MOUSE_MASK,
+			move |uid, raw, reader| {
+				let Some(mouse_event) = MouseReceiverEvent::from_pulse_data(raw) else {return};
+				// movement turns the pointer, everything else is also passed on to whatever's under it
+				let forwarded = MouseReceiverEvent {
+					delta: None,
+					..mouse_event.clone()
+				};
+				if forwarded.buttons_up.is_some()
+					|| forwarded.buttons_down.is_some()
+					|| forwarded.scroll_distance.is_some()
+					|| forwarded.scroll_steps.is_some()
+				{
+					mouse_event_tx.send(MouseEvent::Forward(forwarded));
+				}
+				if let Some(mouse_delta) = mouse_event.delta {
+					let device = mouse_device(uid, &reader);
+					pending_movement.add(&device, Vec2::new(mouse_delta.x, mouse_delta.y));
+				}
+				if let Some(buttons_down) = mouse_event.buttons_down {
+					for button in buttons_down {
+						if let Some(event) = MouseEvent::button(button, true) {
+							mouse_event_tx.send(event);
+						}
+					}
+				}
+				if let Some(buttons_up) = mouse_event.buttons_up {
+					for button in buttons_up {
+						if let Some(event) = MouseEvent::button(button, false) {
+							mouse_event_tx.send(event);
+						}
+					}
+				}
+				if let Some(scroll_distance) = mouse_event.scroll_distance {
+					mouse_event_tx.send(MouseEvent::Scroll {
+						x: scroll_distance.x,
+						y: scroll_distance.y,
+					});
+				}
+				if let Some(scroll_steps) = mouse_event.scroll_steps {
+					mouse_event_tx.send(MouseEvent::ScrollDiscrete {
+						x: scroll_steps.x,
+						y: scroll_steps.y,
+					});
+				}
+			},
+		)?;
+
+		let keyboard_pulse_receiver = InlinePulseReceiver::create(
+			azimuth.pointer(),
+			Transform::default(),
+			&field,
+			&KEYBOARD_MASK,
+			move |_uid, raw, _reader| {
+				let Some(key_event) = KeyboardEvent::from_pulse_data(raw) else {return};
+				keyboard_event_tx.send(key_event);
+			},
+		)?;
+		// azimuth's own receivers sit right on the pointer and would otherwise take every key,
+		// and forwarding mouse events back to itself would loop forever
+		let keyboard_receiver_path = keyboard_pulse_receiver.node().node().get_path()?;
+		let mouse_receiver_path = mouse_pulse_receiver.node().node().get_path()?;
+		azimuth.ignore_receivers(&keyboard_receiver_path, &mouse_receiver_path);
+
+		Ok(Session {
+			azimuth: Arc::new(Mutex::new(azimuth)),
+			_field: field,
+			_mouse_pulse_receiver: mouse_pulse_receiver,
+			_keyboard_pulse_receiver: keyboard_pulse_receiver,
+		})
+	}
+}
+
+/// Which mouse a pulse is from, to keep its movement apart and look it up in [`Settings::devices`] by.
+///
+/// Pulse sender uids change every run, so this is the `device` name the sender puts in its pulses if there is one.
+fn mouse_device(uid: &str, reader: &flexbuffers::MapReader<&[u8]>) -> String {
+	reader
+		.index("device")
+		.ok()
+		.and_then(|device| device.get_str().ok())
+		.unwrap_or(uid)
+		.to_string()
+}
+
+/// Create the pointer and everything on it, then run until the connection ends
+async fn connect(resume: &mut Resume) -> Result<Disconnect> {
+	let (client, event_loop) = Client::connect_with_async_loop().await?;
+	tracing::info!("Connected to the Stardust server");
+
+	let session = Session::create(&client, resume)?;
+	client.wrap_root_raw(&session.azimuth)?;
+	let result = event_loop.await;
+	session.azimuth.lock().save(resume);
+	Ok(match result? {
+		Ok(()) => Disconnect::Stopped,
+		Err(e) => Disconnect::Lost(e),
+	})
+}
@@ -0,0 +1,383 @@
+use crate::{
+	acceleration::Acceleration, focus::FocusMode, hotkey::Hotkey, receivers::EnclosingPolicy,
+};
+use color_eyre::{
+	eyre::{ensure, WrapErr},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+use tokio::sync::watch;
+
+/// The default settings, with comments
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+	/// degrees per pixel of mouse movement
+	pub sensitivity: f32,
+	/// lowest pitch the pointer can reach, in degrees
+	pub min_pitch: f32,
+	/// highest pitch the pointer can reach, in degrees
+	pub max_pitch: f32,
+	/// move the pointer down when the mouse moves up
+	pub invert_y: bool,
+	pub acceleration: Acceleration,
+	/// input handlers hit within this many meters of each other count as the same distance
+	pub tie_epsilon: f32,
+	/// seconds an input handler has to be missed before the pointer counts as having left it
+	pub hover_leave_delay: f64,
+	/// how keyboard focus moves between things that take keyboard input
+	pub keyboard_focus: FocusMode,
+	/// whether keyboard and mouse receivers whose field the pointer is inside can be targeted
+	pub enclosing_receivers: EnclosingPolicy,
+	/// per mouse settings, keyed by the `device` name the mouse sends in its pulses
+	pub devices: HashMap<String, DeviceSettings>,
+	pub cursor: CursorSettings,
+	pub ray: RaySettings,
+}
+impl Default for Settings {
+	fn default() -> Self {
+		Settings {
+			sensitivity: 0.1,
+			min_pitch: -90.0,
+			max_pitch: 90.0,
+			invert_y: false,
+			acceleration: Acceleration::default(),
+			tie_epsilon: 0.0001,
+			hover_leave_delay: 0.1,
+			keyboard_focus: FocusMode::default(),
+			enclosing_receivers: EnclosingPolicy::default(),
+			devices: HashMap::new(),
+			cursor: CursorSettings::default(),
+			ray: RaySettings::default(),
+		}
+	}
+}
+impl Settings {
+	/// `$XDG_CONFIG_HOME/azimuth/config.toml`
+	pub fn default_path() -> Option<PathBuf> {
+		Some(dirs::config_dir()?.join("azimuth").join("config.toml"))
+	}
+
+	/// Load the settings at `path`, falling back to the defaults if there's no file there.
+	///
+	/// Call [`Settings::validate`] after applying any overrides, since they could fix what the file has wrong.
+	pub fn load(path: &Path) -> Result<Self> {
+		if !path.exists() {
+			return Ok(Settings::default());
+		}
+		let contents = std::fs::read_to_string(path)
+			.wrap_err_with(|| format!("Couldn't read settings file {}", path.display()))?;
+		toml::from_str(&contents)
+			.wrap_err_with(|| format!("Couldn't parse settings file {}", path.display()))
+	}
+
+	/// Check for values that parse fine but would break the pointer
+	pub fn validate(&self) -> Result<()> {
+		for (name, value) in [
+			("sensitivity", self.sensitivity),
+			("min_pitch", self.min_pitch),
+			("max_pitch", self.max_pitch),
+		] {
+			ensure!(value.is_finite(), "{name} has to be a number, not {value}");
+		}
+		ensure!(
+			self.cursor.distance > 0.0,
+			"cursor.distance has to be above 0, not {}",
+			self.cursor.distance
+		);
+		for (name, value) in [
+			("tie_epsilon", self.tie_epsilon),
+			("cursor.size", self.cursor.size),
+			("cursor.thickness", self.cursor.thickness),
+			("ray.length", self.ray.length),
+			("ray.thickness", self.ray.thickness),
+		] {
+			ensure!(value >= 0.0, "{name} can't be negative, not {value}");
+		}
+		ensure!(
+			self.hover_leave_delay >= 0.0,
+			"hover_leave_delay can't be negative, not {}",
+			self.hover_leave_delay
+		);
+		self.acceleration.validate()?;
+		for (device, settings) in &self.devices {
+			if let Some(acceleration) = &settings.acceleration {
+				acceleration
+					.validate()
+					.wrap_err_with(|| format!("Invalid settings for device {device}"))?;
+			}
+		}
+		Ok(())
+	}
+
+	/// The acceleration for the mouse named `device`, the global one unless it has its own
+	pub fn acceleration_for(&self, device: &str) -> &Acceleration {
+		self.devices
+			.get(device)
+			.and_then(|device| device.acceleration.as_ref())
+			.unwrap_or(&self.acceleration)
+	}
+
+	/// Poll `path` for changes, sending the new settings every time it's modified.
+	/// Invalid files are logged and skipped so the last valid settings stay in effect.
+	///
+	/// `self` is the starting value, so only changes after this count as new.
+	/// `adjust` is applied to every reloaded file before it's sent, e.g. for command line overrides.
+	pub fn watch(
+		self,
+		path: PathBuf,
+		adjust: impl Fn(&mut Settings) + Send + 'static,
+	) -> watch::Receiver<Settings> {
+		let (tx, rx) = watch::channel(self);
+		tokio::task::spawn(async move {
+			let mut last_modified = modified(&path);
+			let mut interval = tokio::time::interval(Duration::from_millis(500));
+			loop {
+				interval.tick().await;
+				let modified = modified(&path);
+				if modified == last_modified {
+					continue;
+				}
+				last_modified = modified;
+				let settings = Settings::load(&path).and_then(|mut settings| {
+					adjust(&mut settings);
+					settings.validate()?;
+					Ok(settings)
+				});
+				match settings {
+					Ok(settings) => {
+						if tx.send(settings).is_err() {
+							return;
+						}
+					}
+					Err(e) => tracing::error!("Not reloading settings: {e:?}"),
+				}
+			}
+		});
+		rx
+	}
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path).ok()?.modified().ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+	/// use this instead of the global acceleration
+	pub acceleration: Option<Acceleration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CursorSettings {
+	/// radius of the reticle in meters
+	pub size: f32,
+	/// thickness of the reticle lines in meters
+	pub thickness: f32,
+	/// RGBA when not pointing at anything
+	pub color: [f32; 4],
+	/// RGBA when pointing at an input handler
+	pub hover_color: [f32; 4],
+	/// RGBA when pointing at something that takes keyboard input
+	pub keyboard_color: [f32; 4],
+	/// RGBA while select is held
+	pub select_color: [f32; 4],
+	/// RGBA while grab is held
+	pub grab_color: [f32; 4],
+	/// how far in front of the pointer the reticle sits when it isn't snapped to anything, in meters.
+	/// `size` and `thickness` are at this distance, the reticle scales to look the same size at any other.
+	pub distance: f32,
+	/// move the reticle onto whatever the pointer is hitting
+	pub snap: bool,
+}
+impl Default for CursorSettings {
+	fn default() -> Self {
+		CursorSettings {
+			size: 0.0005,
+			thickness: 0.001,
+			color: [1.0; 4],
+			hover_color: [0.5, 1.0, 1.0, 1.0],
+			keyboard_color: [1.0, 0.9, 0.4, 1.0],
+			select_color: [0.4, 1.0, 0.4, 1.0],
+			grab_color: [1.0, 0.6, 0.2, 1.0],
+			distance: 0.1,
+			snap: true,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RaySettings {
+	/// draw a line from the pointer to whatever it's hitting
+	pub visible: bool,
+	/// key that shows or hides the ray, this key won't be sent to anything else
+	pub toggle_key: Option<Hotkey>,
+	/// how long the ray is when it isn't hitting anything, in meters
+	pub length: f32,
+	/// thickness of the ray in meters
+	pub thickness: f32,
+	/// RGBA at the end of the ray, it fades in from the pointer
+	pub color: [f32; 4],
+}
+impl Default for RaySettings {
+	fn default() -> Self {
+		RaySettings {
+			visible: false,
+			toggle_key: None,
+			length: 5.0,
+			thickness: 0.002,
+			color: [1.0, 0.2, 0.2, 1.0],
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::acceleration::{AdaptiveCurve, CustomCurve};
+
+	#[test]
+	fn default_config_is_valid() {
+		let settings: Settings = toml::from_str(DEFAULT_CONFIG).unwrap();
+		assert_eq!(settings, Settings::default());
+		assert!(settings.validate().is_ok());
+	}
+
+	#[test]
+	fn cursor_distance_has_to_be_positive() {
+		let mut settings = Settings::default();
+		settings.cursor.distance = 0.0;
+		assert!(settings.validate().is_err());
+		settings.cursor.distance = f32::NAN;
+		assert!(settings.validate().is_err());
+	}
+
+	#[test]
+	fn sizes_cant_be_negative() {
+		let mut settings = Settings::default();
+		settings.ray.thickness = -0.001;
+		assert!(settings.validate().is_err());
+	}
+
+	#[test]
+	fn curve_points_have_to_be_sorted() {
+		let mut settings = Settings {
+			acceleration: Acceleration::Custom(CustomCurve {
+				points: vec![[1000.0, 2.0], [0.0, 0.5]],
+			}),
+			..Default::default()
+		};
+		assert!(settings.validate().is_err());
+		settings.acceleration = Acceleration::Custom(CustomCurve {
+			points: vec![[0.0, 0.5], [500.0, 1.0], [500.0, 2.0]],
+		});
+		assert!(settings.validate().is_ok());
+	}
+
+	#[test]
+	fn turning_values_have_to_be_numbers() {
+		for value in [f32::NAN, f32::INFINITY] {
+			let mut settings = Settings {
+				sensitivity: value,
+				..Default::default()
+			};
+			assert!(settings.validate().is_err());
+			settings.sensitivity = 0.1;
+			settings.min_pitch = value;
+			assert!(settings.validate().is_err());
+			settings.min_pitch = -90.0;
+			settings.max_pitch = -value;
+			assert!(settings.validate().is_err());
+		}
+	}
+
+	#[test]
+	fn nan_sensitivity_is_rejected_from_toml() {
+		let settings: Settings = toml::from_str("sensitivity = nan").unwrap();
+		assert!(settings.validate().is_err());
+	}
+
+	#[test]
+	fn adaptive_curve_has_to_be_numbers_0_or_above() {
+		let curve = AdaptiveCurve::default();
+		for bad in [
+			AdaptiveCurve {
+				accel: -1.0,
+				..curve.clone()
+			},
+			AdaptiveCurve {
+				threshold: f32::NAN,
+				..curve.clone()
+			},
+			AdaptiveCurve {
+				max_gain: f32::INFINITY,
+				..curve.clone()
+			},
+		] {
+			let settings = Settings {
+				acceleration: Acceleration::Adaptive(bad),
+				..Default::default()
+			};
+			assert!(settings.validate().is_err());
+		}
+	}
+
+	#[test]
+	fn curve_gains_have_to_be_numbers_0_or_above() {
+		for points in [
+			vec![[0.0, 1.0], [1000.0, -1.0]],
+			vec![[0.0, f32::NAN]],
+			vec![[f32::NAN, 1.0]],
+		] {
+			let settings = Settings {
+				acceleration: Acceleration::Custom(CustomCurve { points }),
+				..Default::default()
+			};
+			assert!(settings.validate().is_err());
+		}
+	}
+
+	#[test]
+	fn devices_override_acceleration_by_name() {
+		let settings: Settings = toml::from_str(
+			r#"
+			[devices.trackball.acceleration]
+			profile = "adaptive"
+			threshold = 100.0
+			accel = 1.0
+			max_gain = 2.0
+			"#,
+		)
+		.unwrap();
+		assert!(settings.validate().is_ok());
+		assert!(matches!(
+			settings.acceleration_for("trackball"),
+			Acceleration::Adaptive(_)
+		));
+		assert_eq!(settings.acceleration_for("other"), &settings.acceleration);
+	}
+
+	#[test]
+	fn device_acceleration_is_validated() {
+		let mut settings = Settings::default();
+		settings.devices.insert(
+			"trackball".to_string(),
+			DeviceSettings {
+				acceleration: Some(Acceleration::Custom(CustomCurve {
+					points: vec![[0.0, -1.0]],
+				})),
+			},
+		);
+		assert!(settings.validate().is_err());
+	}
+}